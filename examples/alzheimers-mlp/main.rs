@@ -1,5 +1,13 @@
 use anyhow::Result;
-use bhop::{BhopConfig, SimpleModel};
+use bhop::{
+    checkpoint::{Restore, Retention},
+    minimiser::{AdamMinimiser, Batched},
+    nonfinite::NonFinitePolicy,
+    step::UniformStep,
+    stop::StopCriteria,
+    temperature::TemperatureSchedule,
+    BestBy, BhopConfig, SimpleModel,
+};
 use candle_core::DType;
 use candle_nn::Optimizer;
 use clap::Parser;
@@ -30,18 +38,33 @@ fn main() -> Result<()> {
 
     let args = Args::parse();
 
-    let (model, varmap) = setup_training(args.load)?;
+    let (model, varmap) = setup_training(args.load, args.bhop && args.adam)?;
 
     let l2_reg = Some(args.l2reg);
     let temperature = 1.;
     let pert_range = 1.;
     let lbfgs_steps = 20_000;
 
+    let weight_decay = l2_reg.map(|l| optimisers::Decay::WeightDecay(l * 2.));
+    let adam_params = ParamsAdam {
+        lr: args.lr,
+        beta_1: 0.9,
+        beta_2: 0.999,
+        eps: 1e-8,
+        weight_decay,
+        amsgrad: true,
+    };
+
     if args.bhop {
         let config = BhopConfig {
             steps: args.epochs,
-            temperature,
+            temperature: TemperatureSchedule::Constant(temperature),
             step_size: pert_range,
+            step_taker: Box::new(UniformStep),
+            step_scale: None,
+            mask: None,
+            best_by: BestBy::Loss,
+            adaptive_step: None,
             lbfgs_steps,
             step_conv: optimisers::lbfgs::StepConv::MinStep(0.),
             grad_conv: optimisers::lbfgs::GradConv::MinForce(1e-4),
@@ -49,19 +72,26 @@ fn main() -> Result<()> {
             l2_reg,
             linesearch: Some(LineSearch::StrongWolfe(1e-4, 0.9, 1e-9)),
             seed: 42,
+            retention: Retention::All,
+            restore: Restore::Disk,
+            observer: Box::new(()),
+            stop: StopCriteria::default(),
+            non_finite: NonFinitePolicy::Reject,
+            duplicates: None,
         };
 
-        let _names = bhop::basin_hopping(&model, varmap, "alzheimer_weights", config)?;
-    } else {
-        let weight_decay = l2_reg.map(|l| optimisers::Decay::WeightDecay(l * 2.));
-        let adam_params = ParamsAdam {
-            lr: args.lr,
-            beta_1: 0.9,
-            beta_2: 0.999,
-            eps: 1e-8,
-            weight_decay,
-            amsgrad: true,
+        let _result = if args.adam {
+            // relax over the whole training set, a batch at a time
+            let minimiser = Batched(AdamMinimiser {
+                params: adam_params,
+                steps: lbfgs_steps,
+                loss_tol: 1e-7,
+            });
+            bhop::basin_hopping_with(&model, varmap, "alzheimer_weights", config, minimiser)?
+        } else {
+            bhop::basin_hopping(&model, varmap, "alzheimer_weights", config)?
         };
+    } else {
         let mut optimiser = Adam::new(varmap.all_vars(), adam_params)?;
         println!("Starting training");
         let mut initial_loss = model.loss()?.to_dtype(DType::F32)?.to_scalar::<f32>()?;
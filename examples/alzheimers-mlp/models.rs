@@ -1,10 +1,11 @@
-use bhop::SimpleModel;
+use bhop::{BatchedModel, SimpleModel};
 use candle_core::{DType, Result, Tensor, D};
 use candle_nn::{loss, Conv2d, Linear, VarBuilder};
 use optimisers::Model;
 
 const IMAGE_DIM: usize = 128;
 const LABELS: usize = 4;
+const BATCH_SIZE: usize = 512;
 
 #[derive(Clone)]
 pub struct Mlp {
@@ -77,6 +78,23 @@ impl Model for Mlp {
     }
 }
 
+impl BatchedModel for Mlp {
+    fn n_batches(&self) -> usize {
+        self.train_labels.dim(0).unwrap_or(0).div_ceil(BATCH_SIZE)
+    }
+
+    fn batch_loss(&self, batch: usize) -> Result<Tensor> {
+        let n = self.train_labels.dim(0)?;
+        let start = batch * BATCH_SIZE;
+        let len = BATCH_SIZE.min(n - start);
+        let logits = self.forward(&self.train_data.narrow(0, start, len)?)?;
+        // weight the batch mean by the fraction of the training set in the batch
+        #[allow(clippy::cast_precision_loss)]
+        loss::cross_entropy(&logits, &self.train_labels.narrow(0, start, len)?)?
+            .affine(len as f64 / n as f64, 0.)
+    }
+}
+
 impl Mlp {
     fn forward(&self, input: &Tensor) -> Result<Tensor> {
         let (b_sz, _i, _img_x, _img_y) = input.dims4()?;
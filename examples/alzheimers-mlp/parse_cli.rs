@@ -14,6 +14,10 @@ pub struct Args {
     #[arg(short = 'b', long, default_value_t = false)]
     pub bhop: bool,
 
+    /// relax each basin hopping step with adam rather than lbfgs, over the full training set
+    #[arg(short = 'a', long, default_value_t = false)]
+    pub adam: bool,
+
     /// basin hopping or adam
     #[arg(short = 'd', long)]
     pub load: Option<String>,
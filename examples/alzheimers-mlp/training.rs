@@ -5,7 +5,8 @@ use log::info;
 const NIMAGES: usize = 2048;
 use crate::{load_dataset, models::Mlp, DATATYPE};
 
-pub fn setup_training(load_path: Option<String>) -> anyhow::Result<(Mlp, VarMap)> {
+/// Set up the model, training on the first `NIMAGES` images unless `full` is set
+pub fn setup_training(load_path: Option<String>, full: bool) -> anyhow::Result<(Mlp, VarMap)> {
     // check to see if cuda device availabke
     let dev = candle_core::Device::cuda_if_available(0)?;
     info!("Training on device {dev:?}");
@@ -16,13 +17,14 @@ pub fn setup_training(load_path: Option<String>) -> anyhow::Result<(Mlp, VarMap)
     // let iter = DatasetRandomIter::new(ds, valid, seq_len, device)
     // let batches = Batcher::new2((train_images, train_labels));
 
+    let n_train = if full { train_labels.dim(0)? } else { NIMAGES };
     // get the labels from the dataset
     let train_labels = train_labels
-        .narrow(0, 0, NIMAGES)?
+        .narrow(0, 0, n_train)?
         .to_dtype(DType::U32)?
         .to_device(&dev)?;
 
-    let train_images = train_images.narrow(0, 0, NIMAGES)?.to_device(&dev)?;
+    let train_images = train_images.narrow(0, 0, n_train)?.to_device(&dev)?;
 
     let test_labels = test_labels.to_dtype(DType::U32)?.to_device(&dev)?;
     let test_images = test_images.to_device(&dev)?;
@@ -1,5 +1,12 @@
 use anyhow::Result;
-use bhop::BhopConfig;
+use bhop::{
+    checkpoint::{Restore, Retention},
+    nonfinite::NonFinitePolicy,
+    step::UniformStep,
+    stop::StopCriteria,
+    temperature::TemperatureSchedule,
+    BestBy, BhopConfig,
+};
 use candle_core::DType;
 use env_logger::Builder;
 use log::LevelFilter;
@@ -27,8 +34,13 @@ fn main() -> Result<()> {
 
     let config = BhopConfig {
         steps: 100,
-        temperature,
+        temperature: TemperatureSchedule::Constant(temperature),
         step_size: pert_range,
+        step_taker: Box::new(UniformStep),
+        step_scale: None,
+        mask: None,
+        best_by: BestBy::Loss,
+        adaptive_step: None,
         lbfgs_steps,
         step_conv: optimisers::lbfgs::StepConv::MinStep(0.),
         grad_conv: optimisers::lbfgs::GradConv::MinForce(1e-3),
@@ -36,9 +48,15 @@ fn main() -> Result<()> {
         l2_reg,
         linesearch: Some(LineSearch::StrongWolfe(1e-4, 0.9, 1e-9)),
         seed: 42,
+        retention: Retention::TopK(10),
+        restore: Restore::Memory,
+        observer: Box::new(()),
+        stop: StopCriteria::default(),
+        non_finite: NonFinitePolicy::Reject,
+        duplicates: None,
     };
 
-    let _names = bhop::basin_hopping(&model, varmap, "autoencoder_weights", config)?;
+    let _result = bhop::basin_hopping(&model, varmap, "autoencoder_weights", config)?;
 
     Ok(())
 }
@@ -1,15 +1,22 @@
 use anyhow::Result;
-use bhop::BhopConfig;
-use candle_core::DType;
+use bhop::{
+    checkpoint::{Restore, Retention},
+    compare::compare_minima,
+    nonfinite::NonFinitePolicy,
+    step::UniformStep,
+    stop::StopCriteria,
+    temperature::TemperatureSchedule,
+    BestBy, BhopConfig, Manifest,
+};
+use candle_core::{DType, Tensor};
 use env_logger::Builder;
-use log::LevelFilter;
+use log::{info, LevelFilter};
 use optimisers::lbfgs::LineSearch;
 use training::setup_lbfgs_training;
 
-use crate::misclassification::perturbed_misclassification;
+use crate::models::Mlp;
 
 mod load_mnist;
-mod misclassification;
 mod models;
 mod training;
 
@@ -22,7 +29,7 @@ fn main() -> Result<()> {
     builder.init();
     // let m = load_data().context("Failed to load data")?;
 
-    let (model, varmap) = setup_lbfgs_training()?;
+    let (model, varmap, setup) = setup_lbfgs_training()?;
     let l2_reg = Some(1e-5);
     let temperature = 0.05;
     let pert_range = 1.;
@@ -30,8 +37,13 @@ fn main() -> Result<()> {
 
     let config = BhopConfig {
         steps: 100,
-        temperature,
+        temperature: TemperatureSchedule::Constant(temperature),
         step_size: pert_range,
+        step_taker: Box::new(UniformStep),
+        step_scale: None,
+        mask: None,
+        best_by: BestBy::Loss,
+        adaptive_step: None,
         lbfgs_steps,
         step_conv: optimisers::lbfgs::StepConv::MinStep(0.),
         grad_conv: optimisers::lbfgs::GradConv::MinForce(1e-4),
@@ -39,13 +51,32 @@ fn main() -> Result<()> {
         l2_reg,
         linesearch: Some(LineSearch::StrongWolfe(1e-4, 0.9, 1e-9)),
         seed: 42,
+        retention: Retention::AcceptedOnly,
+        restore: Restore::Disk,
+        observer: Box::new(()),
+        stop: StopCriteria::default(),
+        non_finite: NonFinitePolicy::Reject,
+        duplicates: None,
     };
 
-    let names = bhop::basin_hopping(&model, varmap, "mlp_weights", config)?;
+    bhop::basin_hopping(&model, varmap, "mlp_weights", config)?;
 
-    for name in &names {
-        for name2 in &names {
-            perturbed_misclassification(name, name2, "mlp_weights")?;
+    // compare the minima visited by the walk, as recorded in the run manifest
+    let manifest = Manifest::read("mlp_weights")?;
+    let names: Vec<_> = manifest.accepted().map(|h| h.name.clone()).collect();
+    // predictions are compared on random noise shaped like the training images
+    let inputs = Tensor::randn_like(&setup.train_data, 0., 1.)?;
+    let device = setup.train_data.device().clone();
+    let comparison =
+        compare_minima::<Mlp, _, _>(&names, "mlp_weights", &inputs, setup, DATATYPE, &device)?;
+    for (i, a) in comparison.names.iter().enumerate() {
+        for (j, b) in comparison.names.iter().enumerate() {
+            info!(
+                " pairwise agreement {} {}: {:5.2}%",
+                a,
+                b,
+                100. * comparison.agreement[i][j]
+            );
         }
     }
 
@@ -16,6 +16,7 @@ pub struct Mlp {
     test_labels: Tensor,
 }
 
+#[derive(Clone)]
 pub struct MySetupVars {
     pub train_data: Tensor,
     pub train_labels: Tensor,
@@ -61,7 +62,7 @@ impl SimpleModel for Mlp {
 
 impl Model for Mlp {
     fn loss(&self) -> Result<Tensor> {
-        let logits = self.forward()?;
+        let logits = self.forward(&self.train_data)?;
         // softmax the log probabilities
         // let log_sm = ops::log_softmax(&logits, D::Minus1)?;
         // get the loss
@@ -69,12 +70,9 @@ impl Model for Mlp {
     }
 }
 
-pub trait Forward {
-    fn forward(&self) -> Result<Tensor>;
-}
-impl Forward for Mlp {
-    fn forward(&self) -> Result<Tensor> {
-        let xs = self.ln1.forward(&self.train_data)?;
+impl Module for Mlp {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let xs = self.ln1.forward(xs)?;
         let xs = xs.tanh()?;
         self.ln2.forward(&xs)
     }
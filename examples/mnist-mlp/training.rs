@@ -3,9 +3,12 @@ use candle_core::DType;
 use candle_nn::{VarBuilder, VarMap};
 use log::info;
 
-use crate::{load_mnist, models::Mlp};
+use crate::{
+    load_mnist,
+    models::{Mlp, MySetupVars},
+};
 
-pub fn setup_lbfgs_training() -> anyhow::Result<(Mlp, VarMap)> {
+pub fn setup_lbfgs_training() -> anyhow::Result<(Mlp, VarMap, MySetupVars)> {
     // check to see if cuda device availabke
     let dev = candle_core::Device::cuda_if_available(0)?;
     info!("Training on device {dev:?}");
@@ -31,13 +34,13 @@ pub fn setup_lbfgs_training() -> anyhow::Result<(Mlp, VarMap)> {
     // create a new variable builder
     let vs = VarBuilder::from_varmap(&varmap, DType::F32, &dev);
 
-    let setup = crate::models::MySetupVars {
+    let setup = MySetupVars {
         train_data: train_images,
         train_labels,
         test_data: test_images,
         test_labels,
     };
     // create model from variables
-    let model = Mlp::new(vs.clone(), setup)?;
-    Ok((model, varmap))
+    let model = Mlp::new(vs.clone(), setup.clone())?;
+    Ok((model, varmap, setup))
 }
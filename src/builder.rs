@@ -0,0 +1,420 @@
+/*!
+A builder for [`BhopConfig`], and the checks applied to a config before a run starts
+*/
+
+use optimisers::lbfgs::{GradConv, LineSearch, StepConv};
+
+use crate::checkpoint::{Restore, Retention};
+use crate::duplicates::DuplicateDetection;
+use crate::mask::VarMask;
+use crate::nonfinite::NonFinitePolicy;
+use crate::observer::BhopObserver;
+use crate::step::{AdaptiveStepSize, StepScale, StepTaker, UniformStep};
+use crate::stop::StopCriteria;
+use crate::temperature::TemperatureSchedule;
+use crate::{BestBy, BhopConfig};
+
+/// An invalid setting in a [`BhopConfig`]
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+pub enum ConfigError {
+    /// No basin hopping steps
+    #[error("the number of steps must be positive")]
+    NoSteps,
+    /// A temperature that is not finite and positive, which gives NaN acceptance probabilities
+    /// or cannot be recorded in the manifest
+    #[error("temperatures must be finite and positive, got {0}")]
+    Temperature(f64),
+    /// An exponential temperature schedule with a decay that is not positive
+    #[error("the temperature decay must be positive, got {0}")]
+    TemperatureDecay(f64),
+    /// A step size that is not positive
+    #[error("the step size must be positive, got {0}")]
+    StepSize(f64),
+    /// No L-BFGS steps, so no relaxation
+    #[error("the number of L-BFGS steps must be positive")]
+    NoLbfgsSteps,
+    /// An empty L-BFGS history
+    #[error("the L-BFGS history size must be positive")]
+    NoHistory,
+    /// A negative L2 regularisation factor
+    #[error("the L2 regularisation factor must not be negative, got {0}")]
+    L2Reg(f64),
+    /// A per-variable step scale that is not positive
+    #[error("invalid step scale: {0}")]
+    StepScale(&'static str),
+    /// Adaptive step size settings that would not adapt
+    #[error("invalid adaptive step size settings: {0}")]
+    AdaptiveStep(&'static str),
+    /// A retry policy for non-finite relaxations that would not shrink the step
+    #[error("the non-finite retry factor must be between 0 and 1, got {0}")]
+    NonFiniteRetry(f64),
+    /// A negative or NaN tolerance for duplicate-minimum detection
+    #[error("duplicate detection tolerances must not be negative, got {0}")]
+    DuplicateTolerance(f64),
+    /// Parallel tempering with fewer than two replicas, so nothing to swap
+    #[error("parallel tempering needs at least two temperatures, got {0}")]
+    TooFewReplicas(usize),
+    /// Parallel tempering that never attempts a swap
+    #[error("the swap interval must be positive")]
+    NoSwapInterval,
+    /// No checkpoints written, while restoring the current minimum from disk
+    #[error("checkpoints are required to restore the current minimum from disk")]
+    RestoreWithoutCheckpoints,
+}
+
+impl Default for BhopConfig {
+    /// 100 steps at temperature 1 with a step size of 0.5, as in scipy, relaxing with up to
+    /// 1000 L-BFGS steps and a strong Wolfe line search, and keeping every checkpoint
+    fn default() -> Self {
+        Self {
+            steps: 100,
+            temperature: TemperatureSchedule::Constant(1.),
+            step_size: 0.5,
+            step_taker: Box::new(UniformStep),
+            step_scale: None,
+            mask: None,
+            best_by: BestBy::Loss,
+            adaptive_step: None,
+            lbfgs_steps: 1000,
+            step_conv: StepConv::MinStep(0.),
+            grad_conv: GradConv::MinForce(1e-5),
+            history_size: 10,
+            l2_reg: None,
+            linesearch: Some(LineSearch::StrongWolfe(1e-4, 0.9, 1e-9)),
+            seed: 0,
+            retention: Retention::All,
+            restore: Restore::Disk,
+            observer: Box::new(()),
+            stop: StopCriteria::default(),
+            non_finite: NonFinitePolicy::Reject,
+            duplicates: None,
+        }
+    }
+}
+
+impl BhopConfig {
+    /// A builder starting from the [`Default`] settings
+    pub fn builder() -> BhopConfigBuilder {
+        BhopConfigBuilder::default()
+    }
+
+    /// Check the settings are valid, as done before every run
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.steps == 0 {
+            return Err(ConfigError::NoSteps);
+        }
+        match self.temperature {
+            TemperatureSchedule::Constant(t) | TemperatureSchedule::Logarithmic { start: t } => {
+                check_temperature(t)?;
+            }
+            TemperatureSchedule::Linear { start, end } => {
+                check_temperature(start)?;
+                check_temperature(end)?;
+            }
+            TemperatureSchedule::Exponential { start, decay } => {
+                check_temperature(start)?;
+                if decay.is_nan() || decay <= 0. {
+                    return Err(ConfigError::TemperatureDecay(decay));
+                }
+            }
+            // checked at every step instead
+            TemperatureSchedule::Custom(_) => {}
+        }
+        if self.step_size.is_nan() || self.step_size <= 0. {
+            return Err(ConfigError::StepSize(self.step_size));
+        }
+        match &self.step_scale {
+            Some(StepScale::Rms { min }) if min.is_nan() || *min < 0. => {
+                return Err(ConfigError::StepScale(
+                    "the minimum RMS factor must not be negative",
+                ));
+            }
+            Some(StepScale::Prefix { scales, default })
+                if scales
+                    .iter()
+                    .map(|(_, s)| s)
+                    .chain([default])
+                    .any(|s| s.is_nan() || *s <= 0.) =>
+            {
+                return Err(ConfigError::StepScale("prefix factors must be positive"));
+            }
+            _ => {}
+        }
+        if let Some(adaptive) = self.adaptive_step {
+            if adaptive.interval == 0 {
+                return Err(ConfigError::AdaptiveStep("the interval must be positive"));
+            }
+            if !(adaptive.accept_rate > 0. && adaptive.accept_rate < 1.) {
+                return Err(ConfigError::AdaptiveStep(
+                    "the acceptance rate must be between 0 and 1",
+                ));
+            }
+            if !(adaptive.factor > 0. && adaptive.factor < 1.) {
+                return Err(ConfigError::AdaptiveStep(
+                    "the factor must be between 0 and 1",
+                ));
+            }
+            let min = adaptive.min_step_size.unwrap_or(0.);
+            let max = adaptive.max_step_size.unwrap_or(f64::INFINITY);
+            if min.is_nan() || max.is_nan() || min < 0. || min > max {
+                return Err(ConfigError::AdaptiveStep(
+                    "the step size bounds must be ordered and not negative",
+                ));
+            }
+        }
+        if self.lbfgs_steps == 0 {
+            return Err(ConfigError::NoLbfgsSteps);
+        }
+        if self.history_size == 0 {
+            return Err(ConfigError::NoHistory);
+        }
+        if let Some(l2) = self.l2_reg {
+            if l2.is_nan() || l2 < 0. {
+                return Err(ConfigError::L2Reg(l2));
+            }
+        }
+        if let NonFinitePolicy::Retry { factor, .. } = self.non_finite {
+            if !(factor > 0. && factor < 1.) {
+                return Err(ConfigError::NonFiniteRetry(factor));
+            }
+        }
+        if let Some(duplicates) = self.duplicates {
+            for tol in [duplicates.loss_tol, duplicates.distance_tol] {
+                if tol.is_nan() || tol < 0. {
+                    return Err(ConfigError::DuplicateTolerance(tol));
+                }
+            }
+        }
+        if self.retention == Retention::None && self.restore == Restore::Disk {
+            return Err(ConfigError::RestoreWithoutCheckpoints);
+        }
+        Ok(())
+    }
+}
+
+/// Check a temperature is finite and positive, also done at every step for custom schedules
+pub(crate) fn check_temperature(t: f64) -> Result<(), ConfigError> {
+    if !(t.is_finite() && t > 0.) {
+        Err(ConfigError::Temperature(t))
+    } else {
+        Ok(())
+    }
+}
+
+/// Builds a [`BhopConfig`], checking it is valid
+///
+/// Any setting not given keeps its [`Default`] value
+#[derive(Default)]
+#[must_use]
+pub struct BhopConfigBuilder {
+    config: BhopConfig,
+}
+
+impl BhopConfigBuilder {
+    /// The number of basin hopping steps
+    pub fn steps(mut self, steps: usize) -> Self {
+        self.config.steps = steps;
+        self
+    }
+
+    /// The temperature schedule, or a constant temperature
+    pub fn temperature(mut self, temperature: impl Into<TemperatureSchedule>) -> Self {
+        self.config.temperature = temperature.into();
+        self
+    }
+
+    /// The initial size of each basin hopping step
+    pub fn step_size(mut self, step_size: f64) -> Self {
+        self.config.step_size = step_size;
+        self
+    }
+
+    /// The strategy used to perturb the variables
+    pub fn step_taker(mut self, step_taker: impl StepTaker + 'static) -> Self {
+        self.config.step_taker = Box::new(step_taker);
+        self
+    }
+
+    /// Scale the step size of each variable
+    pub fn step_scale(mut self, step_scale: StepScale) -> Self {
+        self.config.step_scale = Some(step_scale);
+        self
+    }
+
+    /// Perturb and relax only the variables selected by name
+    pub fn mask(mut self, mask: VarMask) -> Self {
+        self.config.mask = Some(mask);
+        self
+    }
+
+    /// How the best step of the run is chosen
+    pub fn best_by(mut self, best_by: BestBy) -> Self {
+        self.config.best_by = best_by;
+        self
+    }
+
+    /// Adapt the step size to target an acceptance rate
+    pub fn adaptive_step(mut self, adaptive_step: AdaptiveStepSize) -> Self {
+        self.config.adaptive_step = Some(adaptive_step);
+        self
+    }
+
+    /// The maximum number of L-BFGS steps in each relaxation
+    pub fn lbfgs_steps(mut self, lbfgs_steps: usize) -> Self {
+        self.config.lbfgs_steps = lbfgs_steps;
+        self
+    }
+
+    /// The L-BFGS step convergence criterion
+    pub fn step_conv(mut self, step_conv: StepConv) -> Self {
+        self.config.step_conv = step_conv;
+        self
+    }
+
+    /// The L-BFGS gradient convergence criterion
+    pub fn grad_conv(mut self, grad_conv: GradConv) -> Self {
+        self.config.grad_conv = grad_conv;
+        self
+    }
+
+    /// The L-BFGS history size
+    pub fn history_size(mut self, history_size: usize) -> Self {
+        self.config.history_size = history_size;
+        self
+    }
+
+    /// The L2 regularisation factor
+    pub fn l2_reg(mut self, l2_reg: f64) -> Self {
+        self.config.l2_reg = Some(l2_reg);
+        self
+    }
+
+    /// The L-BFGS line search, or `None` for a fixed step
+    pub fn linesearch(mut self, linesearch: Option<LineSearch>) -> Self {
+        self.config.linesearch = linesearch;
+        self
+    }
+
+    /// The random seed
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.seed = seed;
+        self
+    }
+
+    /// Which checkpoint files to keep on disk
+    pub fn retention(mut self, retention: Retention) -> Self {
+        self.config.retention = retention;
+        self
+    }
+
+    /// Where the current minimum is restored from when a step is rejected
+    pub fn restore(mut self, restore: Restore) -> Self {
+        self.config.restore = restore;
+        self
+    }
+
+    /// Callbacks for each event of the run
+    pub fn observer(mut self, observer: impl BhopObserver + 'static) -> Self {
+        self.config.observer = Box::new(observer);
+        self
+    }
+
+    /// Conditions for stopping before every step is taken
+    pub fn stop(mut self, stop: StopCriteria) -> Self {
+        self.config.stop = stop;
+        self
+    }
+
+    /// What to do when a relaxation ends with a non-finite loss or weights
+    pub fn non_finite(mut self, non_finite: NonFinitePolicy) -> Self {
+        self.config.non_finite = non_finite;
+        self
+    }
+
+    /// Recognise revisited minima and label each hop with its minimum
+    pub fn duplicates(mut self, duplicates: DuplicateDetection) -> Self {
+        self.config.duplicates = Some(duplicates);
+        self
+    }
+
+    /// Check the settings and build the config
+    pub fn build(self) -> Result<BhopConfig, ConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn err(builder: BhopConfigBuilder) -> Option<ConfigError> {
+        builder.build().err()
+    }
+
+    #[test]
+    fn builder_rejects_invalid_settings() {
+        assert!(BhopConfig::builder().build().is_ok());
+        assert_eq!(
+            err(BhopConfig::builder().steps(0)),
+            Some(ConfigError::NoSteps)
+        );
+        assert_eq!(
+            err(BhopConfig::builder().temperature(0.)),
+            Some(ConfigError::Temperature(0.))
+        );
+        assert_eq!(
+            err(
+                BhopConfig::builder().temperature(TemperatureSchedule::Exponential {
+                    start: 1.,
+                    decay: 0.,
+                })
+            ),
+            Some(ConfigError::TemperatureDecay(0.))
+        );
+        assert_eq!(
+            err(BhopConfig::builder().step_size(-1.)),
+            Some(ConfigError::StepSize(-1.))
+        );
+        assert_eq!(
+            err(BhopConfig::builder().lbfgs_steps(0)),
+            Some(ConfigError::NoLbfgsSteps)
+        );
+        assert_eq!(
+            err(BhopConfig::builder().history_size(0)),
+            Some(ConfigError::NoHistory)
+        );
+        assert_eq!(
+            err(BhopConfig::builder().l2_reg(-1.)),
+            Some(ConfigError::L2Reg(-1.))
+        );
+        assert!(matches!(
+            err(BhopConfig::builder().step_scale(StepScale::Prefix {
+                scales: vec![("w".into(), 0.)],
+                default: 1.,
+            })),
+            Some(ConfigError::StepScale(_))
+        ));
+        assert!(matches!(
+            err(BhopConfig::builder().adaptive_step(AdaptiveStepSize {
+                min_step_size: Some(2.),
+                max_step_size: Some(1.),
+                ..AdaptiveStepSize::default()
+            })),
+            Some(ConfigError::AdaptiveStep(_))
+        ));
+        assert_eq!(
+            err(BhopConfig::builder().duplicates(DuplicateDetection {
+                loss_tol: -1.,
+                distance_tol: 1.,
+            })),
+            Some(ConfigError::DuplicateTolerance(-1.))
+        );
+        assert_eq!(
+            err(BhopConfig::builder()
+                .retention(Retention::None)
+                .restore(Restore::Disk)),
+            Some(ConfigError::RestoreWithoutCheckpoints)
+        );
+    }
+}
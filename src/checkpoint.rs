@@ -0,0 +1,180 @@
+/*!
+Checkpoint retention policies, and where the current minimum is restored from on rejection
+*/
+
+use std::{fs, path::Path};
+
+use candle_core::Tensor;
+use candle_nn::VarMap;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::HopRecord;
+
+/// Where the weights of the current minimum are restored from when a step is rejected
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Restore {
+    /// Reload the checkpoint file of the current minimum
+    #[default]
+    Disk,
+    /// Keep a copy of the current minimum in memory, on the device of the variables
+    ///
+    /// This avoids a disk read per rejection, and means the checkpoint files are not needed
+    /// while the run is in progress, at the cost of holding a second copy of the weights.
+    Memory,
+}
+
+/// Which checkpoint files to keep on disk
+///
+/// Whatever the policy, the checkpoints of the lowest loss, of the best step by
+/// [`BestBy`](crate::BestBy) and of the current minimum are always kept, so the run can be
+/// resumed whichever [`Restore`] it uses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Retention {
+    /// Keep every checkpoint
+    #[default]
+    All,
+    /// Keep only the checkpoints of accepted steps
+    AcceptedOnly,
+    /// Keep only the checkpoints with the k lowest losses
+    TopK(usize),
+    /// Keep only the current and the best checkpoints
+    CurrentAndBest,
+    /// Write no checkpoints at all, only valid when restoring from [`Restore::Memory`]
+    ///
+    /// A run without checkpoints cannot be resumed
+    None,
+}
+
+impl Retention {
+    /// Mark the checkpoints that should no longer be kept as not retained, returning their names
+    ///
+    /// The files are only deleted by [`remove_checkpoints`], once the manifest no longer refers
+    /// to them, so a run killed in between can still be resumed
+    pub(crate) fn apply(
+        self,
+        history: &mut [HopRecord],
+        current: &str,
+        best: &[String],
+    ) -> Vec<String> {
+        let top_k: Vec<String> = match self {
+            Self::TopK(k) => {
+                // non-finite hops were never saved, and NaN losses can sort first
+                let mut by_loss: Vec<_> = history
+                    .iter()
+                    .filter(|h| h.retained && h.loss.is_finite())
+                    .collect();
+                by_loss.sort_by(|a, b| a.loss.total_cmp(&b.loss));
+                by_loss.iter().take(k).map(|h| h.name.clone()).collect()
+            }
+            _ => Vec::new(),
+        };
+        let mut removed = Vec::new();
+        for hop in history.iter_mut().filter(|h| h.retained) {
+            let keep = best.contains(&hop.name)
+                || hop.name == current
+                || match self {
+                    Self::All => true,
+                    Self::AcceptedOnly => hop.outcome.accepted(),
+                    Self::TopK(_) => top_k.contains(&hop.name),
+                    Self::CurrentAndBest | Self::None => false,
+                };
+            if !keep {
+                hop.retained = false;
+                removed.push(hop.name.clone());
+            }
+        }
+        removed
+    }
+}
+
+/// Delete the checkpoints `names` from the output directory `dir`
+pub(crate) fn remove_checkpoints(dir: &Path, names: &[String]) -> std::io::Result<()> {
+    for name in names {
+        debug!("removing checkpoint {}", name);
+        fs::remove_file(dir.join(name))?;
+    }
+    Ok(())
+}
+
+/// A copy of the variables of a varmap, held on the device of each variable
+pub(crate) struct Snapshot(Vec<(String, Tensor)>);
+
+impl Snapshot {
+    pub fn take(varmap: &VarMap) -> candle_core::Result<Self> {
+        let data = varmap.data().lock().unwrap();
+        let tensors = data
+            .iter()
+            .map(|(name, var)| Ok((name.clone(), var.as_tensor().copy()?)))
+            .collect::<candle_core::Result<_>>()?;
+        Ok(Self(tensors))
+    }
+
+    pub fn restore(&self, varmap: &VarMap) -> candle_core::Result<()> {
+        let data = varmap.data().lock().unwrap();
+        for (name, tensor) in &self.0 {
+            let var = data
+                .get(name)
+                .ok_or_else(|| candle_core::Error::CannotFindTensor { path: name.clone() })?;
+            var.set(tensor)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{hop_record, TempDir};
+    use crate::HopOutcome;
+
+    /// The steps retained after applying `retention` with step 3 current and step 1 best,
+    /// and a non-finite step 4
+    fn retained(retention: Retention) -> std::io::Result<Vec<usize>> {
+        let dir = TempDir::new(&format!("retention_{retention:?}"));
+        fs::create_dir_all(&dir)?;
+        let outcomes = [
+            (4., HopOutcome::NewGlobalMin),
+            (1., HopOutcome::NewGlobalMin),
+            (2., HopOutcome::Rejected),
+            (3., HopOutcome::AcceptedMetropolis),
+        ];
+        let mut history: Vec<_> = outcomes
+            .into_iter()
+            .enumerate()
+            .map(|(step, (loss, outcome))| hop_record(step, loss, outcome))
+            .collect();
+        for hop in &history {
+            fs::write(dir.join(&hop.name), [])?;
+        }
+        // a non-finite hop, never saved, whose NaN sorts before every loss
+        history.push(HopRecord {
+            retained: false,
+            ..hop_record(4, -f64::NAN, HopOutcome::NonFinite)
+        });
+        let current = history[3].name.clone();
+        let best = [history[1].name.clone()];
+        let removed = retention.apply(&mut history, &current, &best);
+        // nothing is deleted until the manifest has been written
+        assert!(removed.iter().all(|name| dir.join(name).exists()));
+        remove_checkpoints(&dir, &removed)?;
+        for hop in &history {
+            assert_eq!(dir.join(&hop.name).exists(), hop.retained);
+        }
+        Ok(history
+            .iter()
+            .filter(|h| h.retained)
+            .map(|h| h.step)
+            .collect())
+    }
+
+    #[test]
+    fn retention_always_keeps_current_and_best() -> std::io::Result<()> {
+        assert_eq!(retained(Retention::All)?, [0, 1, 2, 3]);
+        assert_eq!(retained(Retention::AcceptedOnly)?, [0, 1, 3]);
+        assert_eq!(retained(Retention::TopK(2))?, [1, 2, 3]);
+        assert_eq!(retained(Retention::TopK(1))?, [1, 3]);
+        assert_eq!(retained(Retention::CurrentAndBest)?, [1, 3]);
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+/*!
+Pairwise comparison of minima
+
+Loads each checkpoint once into a single model, built from one copy of the data, and compares
+every pair of minima by how often their predictions agree, the L2 distance between their
+variables and the difference in their losses.
+*/
+
+use std::path::Path;
+
+use candle_core::{DType, Device, Tensor, D};
+use candle_nn::{Module, VarBuilder, VarMap};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::duplicates::l2_distance;
+use crate::training::sorted_vars;
+use crate::{Result, SimpleModel};
+
+/// Pairwise matrices over a set of minima, indexed in the order of `names`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Comparison {
+    /// The names of the checkpoints compared
+    pub names: Vec<String>,
+    /// The loss of each minimum, from [`Model::loss`](optimisers::Model::loss), so
+    /// without any L2 term
+    pub losses: Vec<f64>,
+    /// The fraction of the inputs on which the predicted classes, the largest outputs over
+    /// the last dimension, of minima `i` and `j` agree
+    pub agreement: Vec<Vec<f64>>,
+    /// The L2 distance between the flattened variables of minima `i` and `j`
+    pub distance: Vec<Vec<f64>>,
+    /// The loss of minimum `j` minus that of minimum `i`
+    pub loss_difference: Vec<Vec<f64>>,
+}
+
+impl Comparison {
+    /// The number of minima compared
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Whether no minima were compared
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// Compare every pair of the checkpoints `names` in the output directory `dir`
+///
+/// The model is built once from `setup_vars` with variables of `dtype` on `device`, and the
+/// predictions compared are its outputs on `inputs`.
+pub fn compare_minima<M, S, P>(
+    names: &[S],
+    dir: P,
+    inputs: &Tensor,
+    setup_vars: M::SetupVars,
+    dtype: DType,
+    device: &Device,
+) -> Result<Comparison>
+where
+    M: SimpleModel + Module,
+    S: AsRef<str>,
+    P: AsRef<Path>,
+{
+    let dir = dir.as_ref();
+    let mut varmap = VarMap::new();
+    let vs = VarBuilder::from_varmap(&varmap, dtype, device);
+    let model = M::new(vs, setup_vars)?;
+
+    let mut losses = Vec::with_capacity(names.len());
+    let mut predictions = Vec::with_capacity(names.len());
+    let mut weights = Vec::with_capacity(names.len());
+    for name in names {
+        let name = name.as_ref();
+        varmap.load(dir.join(name))?;
+        let loss = model.loss()?.to_dtype(DType::F64)?.to_scalar::<f64>()?;
+        info!("loaded {} with loss {}", name, loss);
+        losses.push(loss);
+        predictions.push(model.forward(inputs)?.argmax(D::Minus1)?);
+        // copied, as loading the next checkpoint overwrites the variables in place
+        weights.push(
+            sorted_vars(&varmap)
+                .iter()
+                .map(|v| v.as_tensor().copy()?.to_device(&Device::Cpu))
+                .collect::<candle_core::Result<Vec<_>>>()?,
+        );
+    }
+
+    let n = names.len();
+    let mut agreement = vec![vec![1.; n]; n];
+    let mut distance = vec![vec![0.; n]; n];
+    for i in 0..n {
+        for j in i + 1..n {
+            let agree = predictions[i]
+                .eq(&predictions[j])?
+                .to_dtype(DType::F64)?
+                .mean_all()?
+                .to_scalar::<f64>()?;
+            let dist = l2_distance(&weights[i], &weights[j])?;
+            info!(
+                "{} and {}: agreement {:5.2}%, distance {}",
+                names[i].as_ref(),
+                names[j].as_ref(),
+                100. * agree,
+                dist
+            );
+            (agreement[i][j], agreement[j][i]) = (agree, agree);
+            (distance[i][j], distance[j][i]) = (dist, dist);
+        }
+    }
+    let loss_difference = losses
+        .iter()
+        .map(|a| losses.iter().map(|b| b - a).collect())
+        .collect();
+
+    Ok(Comparison {
+        names: names.iter().map(|n| n.as_ref().to_string()).collect(),
+        losses,
+        agreement,
+        distance,
+        loss_difference,
+    })
+}
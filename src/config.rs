@@ -0,0 +1,164 @@
+/*!
+Reading and writing a [`BhopConfig`] as TOML or JSON, with the `serde` feature, which also
+enables the optional `toml` dependency
+
+The step taker, observer, custom temperature schedules and stop predicates are code rather
+than data, so are not written: a config read from a file uses [`UniformStep`], no observer
+and no stop predicate, which can then be replaced before the run. Writing a config with a
+[`TemperatureSchedule::Custom`](crate::temperature::TemperatureSchedule::Custom) schedule fails.
+
+The [`StepConv`], [`GradConv`] and [`LineSearch`] enums from candle-optimisers do not implement
+serde, so are written through mirror enums with the same variants.
+*/
+
+use std::{fs, path::Path};
+
+use optimisers::lbfgs::{GradConv, LineSearch, StepConv};
+use serde::{de::Deserializer, ser::Error, Deserialize, Serialize, Serializer};
+
+use crate::observer::BhopObserver;
+use crate::step::{StepTaker, UniformStep};
+use crate::{BhopConfig, Result};
+
+impl BhopConfig {
+    /// Parse a config from a TOML string
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    /// Write the config as a TOML string
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    /// Parse a config from a JSON string
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Write the config as a JSON string
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Read a config file, as TOML if it has a `.toml` extension and as JSON otherwise
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        if is_toml(path) {
+            Self::from_toml(&contents)
+        } else {
+            Self::from_json(&contents)
+        }
+    }
+
+    /// Write the config to a file, as TOML if it has a `.toml` extension and as JSON otherwise
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let contents = if is_toml(path) {
+            self.to_toml()?
+        } else {
+            self.to_json()?
+        };
+        fs::write(path, contents)?;
+        Ok(())
+    }
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "toml")
+}
+
+pub(crate) fn default_step_taker() -> Box<dyn StepTaker> {
+    Box::new(UniformStep)
+}
+
+pub(crate) fn default_observer() -> Box<dyn BhopObserver> {
+    Box::new(())
+}
+
+/// Mirror of [`StepConv`]
+#[derive(Serialize, Deserialize)]
+enum StepConvDef {
+    MinStep(f64),
+    RMSStep(f64),
+}
+
+/// Mirror of [`GradConv`]
+#[derive(Serialize, Deserialize)]
+enum GradConvDef {
+    MinForce(f64),
+    RMSForce(f64),
+}
+
+/// Mirror of [`LineSearch`]
+#[derive(Serialize, Deserialize)]
+enum LineSearchDef {
+    StrongWolfe(f64, f64, f64),
+}
+
+pub(crate) mod step_conv {
+    use super::{Deserialize, Deserializer, Error, Serialize, Serializer, StepConv, StepConvDef};
+
+    pub fn serialize<S: Serializer>(conv: &StepConv, s: S) -> Result<S::Ok, S::Error> {
+        match *conv {
+            StepConv::MinStep(x) => StepConvDef::MinStep(x),
+            StepConv::RMSStep(x) => StepConvDef::RMSStep(x),
+            _ => return Err(S::Error::custom(format!("cannot serialize {conv:?}"))),
+        }
+        .serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<StepConv, D::Error> {
+        Ok(match StepConvDef::deserialize(d)? {
+            StepConvDef::MinStep(x) => StepConv::MinStep(x),
+            StepConvDef::RMSStep(x) => StepConv::RMSStep(x),
+        })
+    }
+}
+
+pub(crate) mod grad_conv {
+    use super::{Deserialize, Deserializer, Error, GradConv, GradConvDef, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(conv: &GradConv, s: S) -> Result<S::Ok, S::Error> {
+        match *conv {
+            GradConv::MinForce(x) => GradConvDef::MinForce(x),
+            GradConv::RMSForce(x) => GradConvDef::RMSForce(x),
+            _ => return Err(S::Error::custom(format!("cannot serialize {conv:?}"))),
+        }
+        .serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<GradConv, D::Error> {
+        Ok(match GradConvDef::deserialize(d)? {
+            GradConvDef::MinForce(x) => GradConv::MinForce(x),
+            GradConvDef::RMSForce(x) => GradConv::RMSForce(x),
+        })
+    }
+}
+
+pub(crate) mod line_search {
+    use super::{
+        Deserialize, Deserializer, Error, LineSearch, LineSearchDef, Serialize, Serializer,
+    };
+
+    #[allow(clippy::ref_option)]
+    pub fn serialize<S: Serializer>(search: &Option<LineSearch>, s: S) -> Result<S::Ok, S::Error> {
+        let def = match *search {
+            None => None,
+            Some(LineSearch::StrongWolfe(c1, c2, tol)) => {
+                Some(LineSearchDef::StrongWolfe(c1, c2, tol))
+            }
+            Some(other) => return Err(S::Error::custom(format!("cannot serialize {other:?}"))),
+        };
+        def.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<LineSearch>, D::Error> {
+        Ok(
+            Option::<LineSearchDef>::deserialize(d)?.map(|def| match def {
+                LineSearchDef::StrongWolfe(c1, c2, tol) => LineSearch::StrongWolfe(c1, c2, tol),
+            }),
+        )
+    }
+}
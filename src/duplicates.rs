@@ -0,0 +1,145 @@
+/*!
+Recognising minima that have already been visited
+
+Basin hopping often falls back into a basin it has seen before. With detection enabled, each
+new minimum is compared to the known ones, and two minima are the same if their losses are
+within `loss_tol` and the L2 distance between their flattened variables is within
+`distance_tol`. Each hop is labelled with the canonical ID of its minimum, numbered in the
+order the minima were first found.
+*/
+
+use std::path::Path;
+
+use candle_core::{DType, Device, Tensor, Var};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{HopRecord, Result};
+
+/// Tolerances for treating two minima as the same
+///
+/// New minima are compared against a copy of the variables of every unique minimum, held on
+/// the CPU for the rest of the run, so memory grows with the number of unique minima times the
+/// size of the model. For large models over long runs, budget for this or leave detection off.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DuplicateDetection {
+    /// The largest difference in loss (including the L2 term) between the same minimum
+    pub loss_tol: f64,
+    /// The largest L2 distance between the variables of the same minimum
+    pub distance_tol: f64,
+}
+
+impl Default for DuplicateDetection {
+    fn default() -> Self {
+        Self {
+            loss_tol: 1e-6,
+            distance_tol: 1e-3,
+        }
+    }
+}
+
+/// A unique minimum, with a copy of its variables on the CPU
+struct KnownMinimum {
+    id: usize,
+    loss: f64,
+    weights: Vec<Tensor>,
+}
+
+/// The unique minima found so far
+pub(crate) struct KnownMinima {
+    detection: DuplicateDetection,
+    minima: Vec<KnownMinimum>,
+    next_id: usize,
+}
+
+impl KnownMinima {
+    pub fn new(detection: DuplicateDetection) -> Self {
+        Self {
+            detection,
+            minima: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Rebuild the known minima of a resumed run from the checkpoints in `dir`
+    ///
+    /// A minimum none of whose checkpoints were kept can no longer be recognised
+    pub fn from_history(
+        detection: DuplicateDetection,
+        history: &[HopRecord],
+        dir: &Path,
+    ) -> Result<Self> {
+        let mut known = Self::new(detection);
+        for hop in history {
+            let Some(id) = hop.minimum else { continue };
+            known.next_id = known.next_id.max(id + 1);
+            if !hop.retained || known.minima.iter().any(|m| m.id == id) {
+                continue;
+            }
+            let mut tensors: Vec<_> =
+                candle_core::safetensors::load(dir.join(&hop.name), &Device::Cpu)?
+                    .into_iter()
+                    .collect();
+            tensors.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+            known.minima.push(KnownMinimum {
+                id,
+                loss: hop.loss,
+                weights: tensors.into_iter().map(|(_, t)| t).collect(),
+            });
+        }
+        let lost = known.next_id - known.minima.len();
+        if lost > 0 {
+            warn!(
+                "{} known minima have no checkpoint, so cannot be recognised",
+                lost
+            );
+        }
+        Ok(known)
+    }
+
+    /// The ID of the minimum held by `vars`, in name order, adding it if it is new
+    pub fn classify(&mut self, loss: f64, vars: &[Var]) -> Result<usize> {
+        let weights = vars
+            .iter()
+            .map(|v| v.as_tensor().to_device(&Device::Cpu))
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        let mut closest: Option<(usize, f64)> = None;
+        for known in &self.minima {
+            if (known.loss - loss).abs() > self.detection.loss_tol {
+                continue;
+            }
+            let distance = l2_distance(&known.weights, &weights)?;
+            debug!("distance to minimum {}: {}", known.id, distance);
+            if distance <= self.detection.distance_tol && closest.is_none_or(|(_, d)| distance < d)
+            {
+                closest = Some((known.id, distance));
+            }
+        }
+        if let Some((id, _)) = closest {
+            return Ok(id);
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.minima.push(KnownMinimum {
+            id,
+            loss,
+            weights: weights
+                .iter()
+                .map(Tensor::copy)
+                .collect::<candle_core::Result<_>>()?,
+        });
+        Ok(id)
+    }
+}
+
+/// The L2 distance between two sets of variables, flattened
+pub(crate) fn l2_distance(a: &[Tensor], b: &[Tensor]) -> candle_core::Result<f64> {
+    let mut sum = 0.;
+    for (a, b) in a.iter().zip(b) {
+        sum += (a.to_dtype(DType::F64)? - b.to_dtype(DType::F64)?)?
+            .sqr()?
+            .sum_all()?
+            .to_scalar::<f64>()?;
+    }
+    Ok(sum.sqrt())
+}
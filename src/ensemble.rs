@@ -0,0 +1,190 @@
+/*!
+Ensemble predictions from the minima collected by a basin hopping run
+
+A set of the retained checkpoints is loaded into instances of the model, and their outputs
+on the same inputs are averaged, weighted equally or by a Boltzmann factor of their loss.
+*/
+
+use std::path::Path;
+
+use candle_core::{DType, Device, Tensor, D};
+use candle_nn::{ops::softmax, Module, VarBuilder, VarMap};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::builder::ConfigError;
+use crate::{BhopResult, Error, HopRecord, Result, SimpleModel};
+
+/// Which minima make up an ensemble, and how they are weighted
+///
+/// Only minima whose checkpoints were retained can be loaded. When the run detected
+/// duplicates, a minimum visited more than once is only included once.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Members {
+    /// The `k` minima with the lowest loss, weighted equally
+    TopK(usize),
+    /// The accepted minima, weighted equally
+    Accepted,
+    /// Every minimum, weighted by `exp(-loss / temperature)`
+    Boltzmann {
+        /// The temperature, in units of the loss
+        temperature: f64,
+    },
+}
+
+/// A model loaded with the weights of one minimum
+pub struct Member<M> {
+    /// The name of the checkpoint, relative to the output directory
+    pub name: String,
+    /// The loss at the minimum, including the L2 term
+    pub loss: f64,
+    /// The weight of the member's output in the average, summing to 1 over the ensemble
+    pub weight: f64,
+    /// The model
+    pub model: M,
+    /// The variables of the model
+    pub varmap: VarMap,
+}
+
+/// An ensemble of models loaded from the checkpoints of a basin hopping run
+pub struct Ensemble<M> {
+    /// The members, in order of increasing loss
+    pub members: Vec<Member<M>>,
+}
+
+impl<M: SimpleModel> Ensemble<M> {
+    /// Load the minima of `result` selected by `members` from the output directory `dir`
+    ///
+    /// Each member builds its model from `setup_vars` with variables of `dtype` on `device`,
+    /// and then loads its checkpoint. `result` may be read from the manifest of a run, or be
+    /// the combined result of a [`multi_start`](crate::multistart::multi_start) run, with
+    /// `dir` its top level output directory.
+    pub fn load<P: AsRef<Path>>(
+        result: &BhopResult,
+        dir: P,
+        members: Members,
+        setup_vars: &M::SetupVars,
+        dtype: DType,
+        device: &Device,
+    ) -> Result<Self>
+    where
+        M::SetupVars: Clone,
+    {
+        let dir = dir.as_ref();
+        let hops = select(result, members)?;
+        let weights = match members {
+            Members::Boltzmann { temperature } => {
+                let min = hops.first().map_or(0., |h| h.loss);
+                hops.iter()
+                    .map(|h| (-(h.loss - min) / temperature).exp())
+                    .collect()
+            }
+            Members::TopK(_) | Members::Accepted => vec![1.; hops.len()],
+        };
+        let total: f64 = weights.iter().sum();
+        let members = hops
+            .into_iter()
+            .zip(weights)
+            .map(|(hop, weight)| {
+                let mut varmap = VarMap::new();
+                let vs = VarBuilder::from_varmap(&varmap, dtype, device);
+                let model = M::new(vs, setup_vars.clone())?;
+                varmap.load(dir.join(&hop.name))?;
+                info!("loaded {} with loss {}", hop.name, hop.loss);
+                Ok(Member {
+                    name: hop.name.clone(),
+                    loss: hop.loss,
+                    weight: weight / total,
+                    model,
+                    varmap,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { members })
+    }
+
+    /// The number of members
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Whether the ensemble has no members, which cannot happen once loaded
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+impl<M: SimpleModel + Module> Ensemble<M> {
+    /// The output of every member on `xs`, in the order of the members
+    pub fn member_predictions(&self, xs: &Tensor) -> Result<Vec<Tensor>> {
+        Ok(self
+            .members
+            .iter()
+            .map(|m| m.model.forward(xs))
+            .collect::<candle_core::Result<_>>()?)
+    }
+
+    /// The weighted average of the outputs of the members on `xs`, such as their logits
+    pub fn predict(&self, xs: &Tensor) -> Result<Tensor> {
+        weighted_mean(&self.members, self.member_predictions(xs)?)
+    }
+
+    /// The weighted average of the softmax of the outputs over the last dimension, which for
+    /// a classifier returning logits gives the ensemble's class probabilities
+    pub fn predict_probs(&self, xs: &Tensor) -> Result<Tensor> {
+        let probs = self
+            .member_predictions(xs)?
+            .iter()
+            .map(|logits| softmax(logits, D::Minus1))
+            .collect::<candle_core::Result<_>>()?;
+        weighted_mean(&self.members, probs)
+    }
+}
+
+/// The retained hops selected by `members`, in order of increasing loss
+fn select(result: &BhopResult, members: Members) -> Result<Vec<&HopRecord>> {
+    if let Members::Boltzmann { temperature } = members {
+        if temperature.is_nan() || temperature <= 0. {
+            return Err(ConfigError::Temperature(temperature).into());
+        }
+    }
+    let mut hops: Vec<_> = result
+        .history
+        .iter()
+        .filter(|h| h.retained && h.loss.is_finite())
+        .filter(|h| members != Members::Accepted || h.outcome.accepted())
+        .collect();
+    hops.sort_by(|a, b| a.loss.total_cmp(&b.loss));
+    // the first, lowest loss, visit of each minimum
+    let mut seen = Vec::new();
+    hops.retain(|h| match h.minimum {
+        Some(id) if seen.contains(&id) => false,
+        Some(id) => {
+            seen.push(id);
+            true
+        }
+        None => true,
+    });
+    if let Members::TopK(k) = members {
+        hops.truncate(k);
+    }
+    if hops.is_empty() {
+        return Err(Error::NoMembers);
+    }
+    Ok(hops)
+}
+
+/// The average of `outputs` weighted by the weights of `members`
+fn weighted_mean<M>(members: &[Member<M>], outputs: Vec<Tensor>) -> Result<Tensor> {
+    let mut mean: Option<Tensor> = None;
+    for (member, output) in members.iter().zip(outputs) {
+        let weighted = (output * member.weight)?;
+        mean = Some(match mean {
+            Some(mean) => (mean + weighted)?,
+            None => weighted,
+        });
+    }
+    mean.ok_or(Error::NoMembers)
+}
@@ -0,0 +1,64 @@
+/*!
+The error type returned by the library
+*/
+
+use std::path::PathBuf;
+
+use crate::builder::ConfigError;
+
+/// Errors from basin hopping runs and the files they read and write
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Reading or writing a checkpoint, manifest or config file failed
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// A tensor operation failed
+    #[error(transparent)]
+    Candle(#[from] candle_core::Error),
+    /// A manifest, result or config file could not be (de)serialized as JSON
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    /// A config file could not be parsed as TOML
+    #[cfg(feature = "serde")]
+    #[error(transparent)]
+    TomlDe(#[from] toml::de::Error),
+    /// A config could not be written as TOML
+    #[cfg(feature = "serde")]
+    #[error(transparent)]
+    TomlSer(#[from] toml::ser::Error),
+    /// The config is invalid
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    /// The output path exists and is not a directory
+    #[error("path {} is not a directory", .0.display())]
+    NotADirectory(PathBuf),
+    /// The checkpoint needed to resume a run was not kept
+    #[error("checkpoint {0} of the current minimum was not kept, so the run cannot be resumed")]
+    MissingCheckpoint(String),
+    /// The loss or weights at a minimum were NaN or infinite
+    #[error("non-finite loss or weights at step {step}, loss {loss}")]
+    NonFiniteLoss {
+        /// The basin hopping step
+        step: usize,
+        /// The loss, including the L2 term
+        loss: f64,
+    },
+    /// The varmap has no variables to optimise
+    #[error("the varmap has no variables")]
+    NoVariables,
+    /// A batched model has no batches
+    #[error("the model has no batches")]
+    NoBatches,
+    /// A multi-start run with no walkers
+    #[error("at least one walker is needed")]
+    NoWalkers,
+    /// No retained checkpoints match the ensemble selection
+    #[error("no retained minima to build an ensemble from")]
+    NoMembers,
+    /// An error from user code, such as a custom local minimiser
+    #[error(transparent)]
+    Other(#[from] Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// The result type of the library
+pub type Result<T> = std::result::Result<T, Error>;
@@ -2,18 +2,51 @@
  Basin Hopping optimisation for use with the candle machine learning framework
 */
 
-use candle_core::{Tensor, Var};
+use candle_core::Tensor;
 use candle_nn::{VarBuilder, VarMap};
 use log::info;
 use optimisers::{
-    lbfgs::{GradConv, LineSearch, ParamsLBFGS, StepConv},
+    lbfgs::{GradConv, LineSearch, StepConv},
     Model,
 };
-use rand::{Rng, SeedableRng};
-use std::{fs, path::Path};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path, time::Instant};
 
-use crate::training::{l2_norm, run_lbfgs_training};
+use crate::checkpoint::{Restore, Retention};
+use crate::duplicates::DuplicateDetection;
+pub use crate::error::{Error, Result};
+pub use crate::manifest::{Manifest, MANIFEST_NAME};
+use crate::mask::{check_mask, VarMask};
+use crate::minimiser::{LbfgsMinimiser, LocalMinimiser};
+use crate::nonfinite::NonFinitePolicy;
+use crate::observer::BhopObserver;
+pub use crate::result::{BestBy, BhopResult, HopOutcome, HopRecord, StopReason};
+use crate::step::{AdaptiveStepSize, StepScale, StepTaker};
+use crate::stop::StopCriteria;
+use crate::temperature::TemperatureSchedule;
+use crate::walker::Walker;
+pub mod builder;
+pub mod checkpoint;
+pub mod compare;
+#[cfg(feature = "serde")]
+pub mod config;
+pub mod duplicates;
+pub mod ensemble;
+mod error;
+pub mod manifest;
+pub mod mask;
+pub mod minimiser;
+pub mod multistart;
+pub mod nonfinite;
+pub mod observer;
+mod result;
+pub mod step;
+pub mod stop;
+pub mod temperature;
+pub mod tempering;
 pub mod training;
+mod walker;
 
 /// Trait needed for the model to be used in the basin hopping optimisation
 /// Requires a new function to create a new model from a variable builder and data
@@ -27,145 +60,1163 @@ pub trait SimpleModel: Sized + Model + Clone {
     fn test_eval(&self) -> candle_core::Result<f32>;
 }
 
-/// test
+/// A model whose loss can be evaluated one batch of the training set at a time
+///
+/// Used by the [`Batched`](minimiser::Batched) minimisers, so models with training sets too
+/// large to evaluate in one go can still be relaxed. The loss of the model is the sum of the
+/// batch losses, so a mean over the training set should be weighted by the size of each batch.
+pub trait BatchedModel: SimpleModel {
+    /// The number of batches in the training set
+    fn n_batches(&self) -> usize;
+    /// The contribution of batch `batch` to the loss
+    fn batch_loss(&self, batch: usize) -> candle_core::Result<Tensor>;
+
+    /// The loss summed over every batch, without tracking gradients
+    ///
+    /// Can be used to implement [`Model::loss`], for reporting: as no graph is kept,
+    /// a model using this can only be relaxed with a [`Batched`](minimiser::Batched) minimiser
+    fn batched_loss(&self) -> candle_core::Result<Tensor> {
+        let mut loss = self.batch_loss(0)?.detach();
+        for batch in 1..self.n_batches() {
+            loss = (loss + self.batch_loss(batch)?.detach())?;
+        }
+        Ok(loss)
+    }
+}
+
+/// The settings of a basin hopping run
+///
+/// Build with [`BhopConfig::builder`] to start from the defaults and check the settings.
+/// With the `serde` feature, a config can be read from and written to TOML or JSON,
+/// see the `config` module
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BhopConfig {
     /// the number of basin hopping steps
     pub steps: usize,
-    /// the temperature for the MC criterion
-    pub temperature: f64,
+    /// the temperature schedule for the MC criterion
+    pub temperature: TemperatureSchedule,
     /// The size of each basin hopping step
     pub step_size: f64,
+    /// The strategy used to perturb the variables between basin hopping steps
+    #[cfg_attr(feature = "serde", serde(skip, default = "config::default_step_taker"))]
+    pub step_taker: Box<dyn StepTaker>,
+    /// Scale the step size of each variable, if set
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub step_scale: Option<StepScale>,
+    /// Perturb and relax only the variables selected by name, if set
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub mask: Option<VarMask>,
+    /// How the best step of the run is chosen, by training loss or by test metric
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub best_by: BestBy,
+    /// Adapt the step size to target an acceptance rate, if set
+    pub adaptive_step: Option<AdaptiveStepSize>,
     /// The number of lbfgs steps
     pub lbfgs_steps: usize,
     /// The step convergence criterion
+    #[cfg_attr(feature = "serde", serde(with = "config::step_conv"))]
     pub step_conv: StepConv,
     /// The gradient convergence criterion
+    #[cfg_attr(feature = "serde", serde(with = "config::grad_conv"))]
     pub grad_conv: GradConv,
     /// The history size for the lbfgs optimiser
     pub history_size: usize,
-    /// The L2 regularisation factor
+    /// The L2 regularisation factor, applied to the variables selected by `mask`
     pub l2_reg: Option<f64>,
     /// the line search method
+    #[cfg_attr(feature = "serde", serde(default, with = "config::line_search"))]
     pub linesearch: Option<LineSearch>,
-    /// The random seed to be used for the Monte Carlo eval
+    /// The random seed used for the Monte Carlo eval and the perturbations
     pub seed: u64,
+    /// Which checkpoint files to keep on disk
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub retention: Retention,
+    /// Where the current minimum is restored from when a step is rejected
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub restore: Restore,
+    /// Callbacks for each event of the run, which may also stop it early
+    #[cfg_attr(feature = "serde", serde(skip, default = "config::default_observer"))]
+    pub observer: Box<dyn BhopObserver>,
+    /// Conditions for stopping before every step is taken
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub stop: StopCriteria,
+    /// What to do when a relaxation ends with a non-finite loss or weights
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub non_finite: NonFinitePolicy,
+    /// Recognise revisited minima and label each hop with its minimum, if set
+    ///
+    /// Keeps a CPU copy of the variables of every unique minimum, see [`DuplicateDetection`]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub duplicates: Option<DuplicateDetection>,
 }
 
 /// Run basin hopping global minimisation
+///
+/// The minimised weights of every step are saved to `path`, subject to `config.retention`,
+/// along with a [`Manifest`] recording the run, which also allows it to be continued by
+/// [`resume_basin_hopping`]. Each basin is relaxed with L-BFGS, using the settings in `config`.
 pub fn basin_hopping<M: SimpleModel, P: AsRef<Path>>(
+    model: &M,
+    varmap: VarMap,
+    path: P,
+    config: BhopConfig,
+) -> Result<BhopResult> {
+    let minimiser = LbfgsMinimiser::from(&config);
+    basin_hopping_with(model, varmap, path, config, minimiser)
+}
+
+/// Run basin hopping global minimisation, relaxing each basin with `minimiser`
+///
+/// As [`basin_hopping`], except the L-BFGS settings in `config` are ignored
+pub fn basin_hopping_with<M: SimpleModel, P: AsRef<Path>, L: LocalMinimiser<M>>(
+    model: &M,
+    varmap: VarMap,
+    path: P,
+    config: BhopConfig,
+    minimiser: L,
+) -> Result<BhopResult> {
+    let path: &Path = path.as_ref();
+    config.validate()?;
+    check_mask(&varmap, config.mask.as_ref())?;
+    create_output_dir(path)?;
+    let manifest = Manifest::new(&config);
+    run(model, varmap, path, config, manifest, minimiser)
+}
+
+/// Continue a basin hopping run from the manifest in its output directory
+///
+/// The weights of the current minimum are loaded into `varmap`, and the global minimum,
+/// random number generator, step size and step counter are restored, so the run continues
+/// exactly as if it had not been interrupted. `config.steps` is the total number of steps
+/// including those already taken, so it may be increased to extend a finished run.
+pub fn resume_basin_hopping<M: SimpleModel, P: AsRef<Path>>(
+    model: &M,
+    varmap: VarMap,
+    path: P,
+    config: BhopConfig,
+) -> Result<BhopResult> {
+    let minimiser = LbfgsMinimiser::from(&config);
+    resume_basin_hopping_with(model, varmap, path, config, minimiser)
+}
+
+/// Continue a basin hopping run, relaxing each basin with `minimiser`
+///
+/// As [`resume_basin_hopping`], except the L-BFGS settings in `config` are ignored
+pub fn resume_basin_hopping_with<M: SimpleModel, P: AsRef<Path>, L: LocalMinimiser<M>>(
     model: &M,
     mut varmap: VarMap,
     path: P,
     config: BhopConfig,
-) -> anyhow::Result<Vec<String>> {
+    minimiser: L,
+) -> Result<BhopResult> {
     let path: &Path = path.as_ref();
+    config.validate()?;
+    check_mask(&varmap, config.mask.as_ref())?;
+    if !path.is_dir() {
+        return Err(Error::NotADirectory(path.to_path_buf()));
+    }
+    let mut manifest = Manifest::read(path)?;
+    manifest.config = (&config).into();
+    manifest.result.stop_reason = None;
+    manifest.result.best_by = config.best_by;
+    info!(
+        "resuming from step {}, current minimum {} in {}",
+        manifest.next_step, manifest.current_loss, manifest.current_name
+    );
+    if manifest.next_step > 0 {
+        let current = &manifest.current_name;
+        if !manifest.result.retained_names().any(|n| n == current) {
+            return Err(Error::MissingCheckpoint(current.clone()));
+        }
+        varmap.load(path.join(current))?;
+    }
+    run(model, varmap, path, config, manifest, minimiser)
+}
+
+fn run<M: SimpleModel>(
+    model: &M,
+    varmap: VarMap,
+    path: &Path,
+    mut config: BhopConfig,
+    state: Manifest,
+    mut minimiser: impl LocalMinimiser<M>,
+) -> Result<BhopResult> {
+    let started = Instant::now();
+    let mut walker = Walker::new(model, varmap, path, state, &config)?;
+    let mut stop_reason = StopReason::Completed;
+    for i in walker.state.next_step..config.steps {
+        let temperature = config.temperature.temperature(i, config.steps);
+        if walker
+            .hop(&mut config, &mut minimiser, i, temperature)?
+            .is_break()
+        {
+            stop_reason = StopReason::Observer;
+            break;
+        }
+        if let Some(reason) = config.stop.check(&walker.state.result, started.elapsed()) {
+            info!("stopping early after step {}: {:?}", i, reason);
+            stop_reason = reason;
+            break;
+        }
+    }
+    walker.state.result.stop_reason = Some(stop_reason);
+    if !walker.state.result.history.is_empty() {
+        walker.state.write(path)?;
+    }
+    let result = walker.state.result;
+    info!("final min loss: {}", result.min_loss);
+    info!("final min name: {}", result.min_name);
+    info!(
+        "accepted {} of {} steps",
+        result.n_accepted,
+        result.n_hops()
+    );
+    info!("final step size: {}\n", result.step_size);
+    Ok(result)
+}
+
+/// Create the output directory if it does not already exist
+pub(crate) fn create_output_dir(path: &Path) -> Result<()> {
     if path.exists() {
         if !path.is_dir() {
-            anyhow::bail!(
-                "Path {} exists and is not a directory",
-                path.to_string_lossy()
-            );
+            return Err(Error::NotADirectory(path.to_path_buf()));
         }
     } else {
         fs::create_dir_all(path)?;
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minimiser::{AdamMinimiser, Batched};
+    use crate::multistart::{multi_start, MultiStartConfig, MultiStartResult};
+    use crate::step::StepTaker;
+    use crate::step::UniformStep;
+    use crate::tempering::{parallel_tempering, TemperingConfig};
+    use candle_core::{DType, Device, Var};
+    use candle_nn::Init;
+    use optimisers::adam::ParamsAdam;
+    use optimisers::lbfgs::{GradConv, StepConv};
+    use std::cell::RefCell;
+    use std::ops::ControlFlow;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+
+    /// A path in the temporary directory, removed when dropped so failing tests don't leak it
+    pub(crate) struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("bhop_{}_{}", name, std::process::id())))
+        }
+    }
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
 
-    let mut min_loss = f64::INFINITY;
-    let mut min_name = " ".to_string();
-    let mut names: Vec<String> = Vec::new();
-
-    let mut current_loss = f64::INFINITY;
-    let mut current_name = " ".to_string();
-    let mut rng = rand_xoshiro::Xoshiro256StarStar::seed_from_u64(config.seed);
-
-    for i in 0..config.steps {
-        info!("Epoch {}", i);
-        let name = format!("model_{:03}.st", i);
-        let save_path = path.join(&name);
-        let lbfgs_params = ParamsLBFGS {
-            lr: 1.,
-            history_size: config.history_size,
-            line_search: config.linesearch,
-            step_conv: config.step_conv,
-            grad_conv: config.grad_conv,
-            weight_decay: config.l2_reg.map(|x| 2. * x),
-        };
-        let f = run_lbfgs_training(model, &varmap, lbfgs_params, config.lbfgs_steps)?;
-
-        #[allow(clippy::cast_possible_truncation)]
-        let l2_fac = if let Some(reg) = config.l2_reg {
-            (l2_norm(&varmap.all_vars())? * reg) as f64
-        } else {
-            0.
-        };
-        info!("loss inc L2: {}", f + l2_fac);
-        info!("L2 reg: {}", l2_fac);
-        varmap.save(&save_path)?;
-
-        // Metropolis Hastings
-        if f + l2_fac < min_loss {
-            // new minimum
-            info!("new global min from {} to {}", min_loss, f + l2_fac);
-            info!(
-                "STEP: decrease in loss from {} to {}",
-                current_loss,
-                f + l2_fac
-            );
-            min_loss = f + l2_fac;
-            min_name = name.clone();
-            // by definition lower than previous value
-            current_loss = f + l2_fac;
-            current_name = name.clone();
-        } else if f + l2_fac < current_loss {
-            info!(
-                "STEP: decrease in loss from {} to {}",
-                current_loss,
-                f + l2_fac
-            );
-            current_loss = f + l2_fac;
-            current_name = name.clone();
-        } else {
-            let delta = f + l2_fac - current_loss;
-            let p = (-delta / config.temperature).exp(); // T = temp in units of Kb so P = exp(-delta/T)
-            let n = rng.gen_range(0_f64..1.);
-            if n < p {
-                info!("STEP: accepted MH, from {} to {}", current_loss, f + l2_fac);
-                current_loss = f + l2_fac;
-                current_name = name.clone();
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for TempDir {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = if self.0.is_dir() {
+                fs::remove_dir_all(&self.0)
             } else {
-                // reject
-                info!(
-                    "NOSTEP: rejected MH, loss {}, proposed {}",
-                    current_loss,
-                    f + l2_fac
-                );
-                let current_path = path.join(&current_name);
-                varmap.load(&current_path)?;
+                fs::remove_file(&self.0)
+            };
+        }
+    }
+
+    /// The record of a retained step, with its checkpoint named after the step
+    pub(crate) fn hop_record(step: usize, loss: f64, outcome: HopOutcome) -> HopRecord {
+        HopRecord {
+            step,
+            name: format!("model_{step:03}.st"),
+            retained: true,
+            loss,
+            l2: 0.,
+            fn_evals: 1,
+            converged: true,
+            step_size: 1.,
+            temperature: 1.,
+            outcome,
+            test_metric: None,
+            minimum: None,
+            timestamp: 0,
+        }
+    }
+
+    /// A new model with F64 variables on the CPU
+    fn new_model<M: SimpleModel<SetupVars = ()>>() -> candle_core::Result<(M, VarMap)> {
+        let varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&varmap, DType::F64, &Device::Cpu);
+        Ok((M::new(vs, ())?, varmap))
+    }
+
+    /// A new model and a temporary output directory for its run
+    fn fixture<M: SimpleModel<SetupVars = ()>>(
+        name: &str,
+    ) -> candle_core::Result<(M, VarMap, TempDir)> {
+        let (model, varmap) = new_model()?;
+        Ok((model, varmap, TempDir::new(name)))
+    }
+
+    /// A sum of independent wells, with many local minima
+    #[derive(Clone)]
+    struct Wells {
+        w: Tensor,
+    }
+
+    impl SimpleModel for Wells {
+        type SetupVars = ();
+
+        fn new(vs: VarBuilder, _setup_vars: ()) -> candle_core::Result<Self> {
+            let w = vs.get_with_hints(8, "w", Init::Const(0.5))?;
+            Ok(Self { w })
+        }
+
+        fn test_eval(&self) -> candle_core::Result<f32> {
+            self.loss()?.to_dtype(DType::F32)?.to_scalar::<f32>()
+        }
+    }
+
+    impl Model for Wells {
+        fn loss(&self) -> candle_core::Result<Tensor> {
+            ((&self.w * 3.)?.sin()? + (self.w.sqr()? * 0.1)?)?.sum_all()
+        }
+    }
+
+    impl BatchedModel for Wells {
+        fn n_batches(&self) -> usize {
+            2
+        }
+
+        fn batch_loss(&self, batch: usize) -> candle_core::Result<Tensor> {
+            let w = self.w.narrow(0, 4 * batch, 4)?;
+            ((&w * 3.)?.sin()? + (w.sqr()? * 0.1)?)?.sum_all()
+        }
+    }
+
+    fn config(seed: u64) -> BhopConfig {
+        BhopConfig {
+            steps: 6,
+            temperature: TemperatureSchedule::Constant(1.),
+            step_size: 1.,
+            step_taker: Box::new(UniformStep),
+            step_scale: None,
+            mask: None,
+            best_by: BestBy::Loss,
+            adaptive_step: None,
+            lbfgs_steps: 100,
+            step_conv: StepConv::MinStep(0.),
+            grad_conv: GradConv::MinForce(1e-5),
+            history_size: 5,
+            l2_reg: None,
+            linesearch: Some(LineSearch::StrongWolfe(1e-4, 0.9, 1e-9)),
+            seed,
+            retention: Retention::All,
+            restore: Restore::Disk,
+            observer: Box::new(()),
+            stop: StopCriteria::default(),
+            non_finite: NonFinitePolicy::Reject,
+            duplicates: None,
+        }
+    }
+
+    /// run basin hopping and return the loss of every step
+    fn loss_sequence(seed: u64, dir: &str) -> anyhow::Result<Vec<f64>> {
+        losses_with(config(seed), dir)
+    }
+
+    fn losses_with(config: BhopConfig, dir: &str) -> anyhow::Result<Vec<f64>> {
+        let (model, varmap, path) = fixture::<Wells>(dir)?;
+        let result = basin_hopping(&model, varmap, &path, config)?;
+        Ok(result.history.iter().map(|h| h.loss).collect())
+    }
+
+    #[test]
+    fn resume_matches_uninterrupted() -> anyhow::Result<()> {
+        let full = loss_sequence(3, "resume_full")?;
+
+        let (model, varmap, path) = fixture::<Wells>("resume")?;
+        let mut first = config(3);
+        first.steps = 3;
+        basin_hopping(&model, varmap, &path, first)?;
+        // the model would be rebuilt from scratch after an interruption
+        let (model, varmap) = new_model::<Wells>()?;
+        let result = resume_basin_hopping(&model, varmap, &path, config(3))?;
+
+        let resumed: Vec<_> = result.history.iter().map(|h| h.loss).collect();
+        assert_eq!(full, resumed);
+        Ok(())
+    }
+
+    #[test]
+    fn resume_after_memory_restore_with_top_k() -> anyhow::Result<()> {
+        let memory_top_k = |steps| {
+            let mut config = config(3);
+            config.steps = steps;
+            // hot enough that the current minimum after five steps is not the lowest
+            config.temperature = TemperatureSchedule::Constant(100.);
+            config.retention = Retention::TopK(1);
+            config.restore = Restore::Memory;
+            config
+        };
+        let full = losses_with(memory_top_k(8), "resume_top_k_full")?;
+
+        let (model, varmap, path) = fixture::<Wells>("resume_top_k")?;
+        basin_hopping(&model, varmap, &path, memory_top_k(5))?;
+        let (model, varmap) = new_model::<Wells>()?;
+        let result = resume_basin_hopping(&model, varmap, &path, memory_top_k(8))?;
+
+        let resumed: Vec<_> = result.history.iter().map(|h| h.loss).collect();
+        assert_eq!(full, resumed);
+        Ok(())
+    }
+
+    #[test]
+    fn retention_keeps_current_and_best() -> anyhow::Result<()> {
+        let (model, varmap, path) = fixture::<Wells>("retention")?;
+        let mut config = config(5);
+        config.retention = Retention::CurrentAndBest;
+        let result = basin_hopping(&model, varmap, &path, config)?;
+        let on_disk = fs::read_dir(&path)?
+            .filter(|f| f.as_ref().is_ok_and(|f| f.file_name() != MANIFEST_NAME))
+            .count();
+
+        assert!(on_disk <= 2);
+        assert_eq!(on_disk, result.retained_names().count());
+        assert!(result.retained_names().any(|n| n == result.min_name));
+        Ok(())
+    }
+
+    #[test]
+    fn manifest_records_the_run() -> anyhow::Result<()> {
+        let (model, varmap, path) = fixture::<Wells>("manifest")?;
+        let before = manifest::timestamp();
+        let result = basin_hopping(&model, varmap, &path, config(6))?;
+        let manifest = Manifest::read(&path)?;
+
+        assert_eq!(manifest.config, (&config(6)).into());
+        assert!(before <= manifest.started_at);
+        assert!(manifest.started_at <= manifest.updated_at);
+        assert!(manifest.updated_at <= manifest::timestamp());
+        assert_eq!(manifest.next_step, 6);
+        assert_eq!(manifest.result.history, result.history);
+        assert_eq!(manifest.best_name(), result.min_name);
+        assert!(!path.join(format!("{MANIFEST_NAME}.tmp")).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn memory_restore_matches_disk() -> anyhow::Result<()> {
+        let disk = loss_sequence(11, "restore_disk")?;
+        let mut config = config(11);
+        config.restore = Restore::Memory;
+        config.retention = Retention::None;
+        let memory = losses_with(config, "restore_memory")?;
+        assert_eq!(disk, memory);
+        Ok(())
+    }
+
+    #[test]
+    fn parallel_tempering_runs_every_replica() -> anyhow::Result<()> {
+        let (model, varmap, path) = fixture::<Wells>("tempering")?;
+        let tempering = TemperingConfig {
+            temperatures: vec![0.1, 1., 10.],
+            swap_interval: 1,
+        };
+        let result = parallel_tempering(&model, varmap, &(), &path, config(2), tempering)?;
+
+        assert_eq!(result.replicas.len(), 3);
+        assert!(result.replicas.iter().all(|r| r.n_hops() == 6));
+        assert_eq!(result.swap_attempts.iter().sum::<usize>(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn adam_minimiser_relaxes_every_hop() -> anyhow::Result<()> {
+        let (model, varmap, path) = fixture::<Wells>("adam")?;
+        let initial = model.loss()?.to_scalar::<f64>()?;
+        let minimiser = AdamMinimiser {
+            params: ParamsAdam {
+                lr: 0.05,
+                ..Default::default()
+            },
+            steps: 500,
+            loss_tol: 1e-9,
+        };
+        let result = basin_hopping_with(&model, varmap, &path, config(4), minimiser)?;
+
+        assert_eq!(result.n_hops(), 6);
+        assert!(result.min_loss < initial);
+        Ok(())
+    }
+
+    #[test]
+    fn batched_matches_full_batch() -> anyhow::Result<()> {
+        let adam = AdamMinimiser {
+            params: ParamsAdam {
+                lr: 0.05,
+                ..Default::default()
+            },
+            steps: 200,
+            loss_tol: 0.,
+        };
+        let mut losses = Vec::new();
+        for batched in [false, true] {
+            let (model, varmap, path) = fixture::<Wells>(&format!("batched_{batched}"))?;
+            let mut config = config(8);
+            config.steps = 2;
+            let result = if batched {
+                basin_hopping_with(&model, varmap, &path, config, Batched(adam.clone()))?
+            } else {
+                basin_hopping_with(&model, varmap, &path, config, adam.clone())?
+            };
+            losses.push(result.history[0].loss);
+        }
+        assert!((losses[0] - losses[1]).abs() < 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn multi_start_merges_walkers() -> anyhow::Result<()> {
+        let path = TempDir::new("multistart");
+        let multi = MultiStartConfig {
+            walkers: 3,
+            threads: Some(2),
+        };
+        let result = multi_start::<Wells, _, _>(
+            &(),
+            DType::F64,
+            &Device::Cpu,
+            &path,
+            || {
+                let mut config = config(6);
+                config.steps = 3;
+                // the last step of every walker diverges
+                config.step_taker = nan_step(&[2]);
+                config
+            },
+            multi,
+        )?;
+        let best_on_disk = path.join(&result.combined.min_name).is_file();
+        let written = MultiStartResult::read(&path)?;
+
+        assert_eq!(result.walkers.len(), 3);
+        assert_eq!(result.combined.n_hops(), 9);
+        assert!(result.walkers.iter().all(|w| w.n_non_finite == 1));
+        assert_eq!(result.combined.n_non_finite, 3);
+        let (_, best) = result.best().unwrap();
+        assert_eq!(result.combined.min_loss, best.min_loss);
+        assert!(best_on_disk);
+        // compared as JSON, as the NaN losses of the diverged steps are never equal
+        assert_eq!(
+            serde_json::to_value(&written)?,
+            serde_json::to_value(&result)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn multi_start_same_seed_same_losses() -> anyhow::Result<()> {
+        let losses = |dir: &str, threads| -> Result<Vec<f64>> {
+            let path = TempDir::new(dir);
+            let multi = MultiStartConfig {
+                walkers: 3,
+                threads: Some(threads),
+            };
+            let result = multi_start::<Wells, _, _>(
+                &(),
+                DType::F64,
+                &Device::Cpu,
+                &path,
+                || config(8),
+                multi,
+            )?;
+            Ok(result.combined.history.iter().map(|h| h.loss).collect())
+        };
+
+        // the walkers start from the same constant weights, so only the hops vary
+        assert_eq!(losses("multistart_a", 1)?, losses("multistart_b", 3)?);
+        Ok(())
+    }
+
+    /// counts the events of a run, stopping it before step `stop_at`
+    struct Counter {
+        stop_at: usize,
+        events: Rc<RefCell<[usize; 4]>>,
+    }
+
+    impl BhopObserver for Counter {
+        fn on_hop_start(&mut self, step: usize, _temperature: f64) -> ControlFlow<()> {
+            if step == self.stop_at {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
             }
         }
-        names.push(name);
-        perturb(&mut varmap.all_vars(), config.step_size)?;
+
+        fn on_lbfgs_step(&mut self, _iteration: usize, _loss: f64) -> ControlFlow<()> {
+            self.events.borrow_mut()[0] += 1;
+            ControlFlow::Continue(())
+        }
+
+        fn on_accept(&mut self, _record: &HopRecord, _varmap: &VarMap) -> ControlFlow<()> {
+            self.events.borrow_mut()[1] += 1;
+            ControlFlow::Continue(())
+        }
+
+        fn on_reject(&mut self, _record: &HopRecord) -> ControlFlow<()> {
+            self.events.borrow_mut()[2] += 1;
+            ControlFlow::Continue(())
+        }
+
+        fn on_new_global_min(&mut self, _record: &HopRecord, _varmap: &VarMap) -> ControlFlow<()> {
+            self.events.borrow_mut()[3] += 1;
+            ControlFlow::Continue(())
+        }
     }
-    info!("final min loss: {}", min_loss);
-    info!("final min name: {}\n", min_name);
-    Ok(names)
-}
 
-fn perturb(vs: &mut Vec<Var>, range: f64) -> candle_core::Result<()> {
-    for v in vs {
-        let pert = Tensor::rand_like(v.as_tensor(), -range, range)?;
-        v.set(&v.add(&pert)?)?;
+    #[test]
+    fn observer_sees_events_and_stops_run() -> anyhow::Result<()> {
+        let events = Rc::new(RefCell::new([0; 4]));
+        let mut config = config(9);
+        config.observer = Box::new(Counter {
+            stop_at: 4,
+            events: events.clone(),
+        });
+        let (model, varmap, path) = fixture::<Wells>("observer")?;
+        let result = basin_hopping(&model, varmap, &path, config)?;
+        let manifest = Manifest::read(&path)?;
+
+        let [lbfgs_steps, accepted, rejected, new_min] = *events.borrow();
+        assert_eq!(result.n_hops(), 4);
+        assert_eq!(result.stop_reason, Some(StopReason::Observer));
+        assert_eq!(manifest.next_step, 4);
+        assert!(lbfgs_steps > 0);
+        assert_eq!(accepted, result.n_accepted);
+        assert_eq!(rejected, result.n_rejected());
+        let new_mins = result
+            .history
+            .iter()
+            .filter(|h| h.outcome == HopOutcome::NewGlobalMin)
+            .count();
+        assert_eq!(new_min, new_mins);
+        Ok(())
     }
-    Ok(())
-}
 
-#[cfg(test)]
-mod tests {
-    // use super::*;
+    #[test]
+    fn stop_criteria_end_run_early() -> anyhow::Result<()> {
+        let path = TempDir::new("stop");
+        let run = |stop: StopCriteria| -> Result<BhopResult> {
+            let (model, varmap) = new_model::<Wells>()?;
+            let mut config = config(12);
+            config.steps = 30;
+            config.stop = stop;
+            basin_hopping(&model, varmap, &path, config)
+        };
+
+        let no_improvement = run(StopCriteria {
+            no_improvement: Some(2),
+            ..Default::default()
+        })?;
+        let predicate = run(StopCriteria {
+            predicate: Some(Box::new(|r| r.n_hops() == 3)),
+            ..Default::default()
+        })?;
+        let manifest = Manifest::read(&path)?;
+        let completed = run(StopCriteria::default())?;
+
+        assert_eq!(no_improvement.stop_reason, Some(StopReason::NoImprovement));
+        assert!(no_improvement.n_hops() < 30);
+        let last_two = &no_improvement.history[no_improvement.n_hops() - 2..];
+        assert!(last_two
+            .iter()
+            .all(|h| h.outcome != HopOutcome::NewGlobalMin));
+        assert_eq!(predicate.stop_reason, Some(StopReason::Predicate));
+        assert_eq!(predicate.n_hops(), 3);
+        assert_eq!(manifest.result.stop_reason, Some(StopReason::Predicate));
+        assert_eq!(completed.stop_reason, Some(StopReason::Completed));
+        assert_eq!(completed.n_hops(), 30);
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn config_round_trips_through_files() -> anyhow::Result<()> {
+        use crate::manifest::ManifestConfig;
+
+        let mut config = config(13);
+        config.temperature = TemperatureSchedule::Exponential {
+            start: 2.,
+            decay: 0.9,
+        };
+        config.stop.no_improvement = Some(3);
+        config.retention = Retention::TopK(4);
+        config.step_scale = Some(crate::step::StepScale::Prefix {
+            scales: vec![("w".into(), 0.5)],
+            default: 1.,
+        });
+        let dir = TempDir::new("config");
+        fs::create_dir_all(&dir)?;
+        for name in ["config.toml", "config.json"] {
+            config.write(dir.join(name))?;
+            let read = BhopConfig::read(dir.join(name))?;
+            assert_eq!(ManifestConfig::from(&read), ManifestConfig::from(&config));
+        }
+
+        config.temperature = TemperatureSchedule::Custom(Box::new(|_| 1.));
+        assert!(config.to_toml().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_config_leaves_output_dir_alone() -> anyhow::Result<()> {
+        let (model, varmap, path) = fixture::<Wells>("invalid")?;
+        let mut invalid = config(1);
+        invalid.temperature = TemperatureSchedule::Constant(-1.);
+        assert!(matches!(
+            basin_hopping(&model, varmap, &path, invalid),
+            Err(Error::Config(builder::ConfigError::Temperature(_)))
+        ));
+        assert!(!path.exists());
+
+        let (model, varmap) = new_model::<Wells>()?;
+        let tempering = TemperingConfig {
+            temperatures: vec![1., 2.],
+            swap_interval: 0,
+        };
+        assert!(matches!(
+            parallel_tempering(&model, varmap, &(), &path, config(1), tempering),
+            Err(Error::Config(builder::ConfigError::NoSwapInterval))
+        ));
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn custom_temperature_checked_every_step() -> anyhow::Result<()> {
+        let (model, varmap, path) = fixture::<Wells>("custom_temperature")?;
+        let mut config = config(4);
+        config.temperature =
+            TemperatureSchedule::Custom(Box::new(|step| if step < 3 { 1. } else { f64::NAN }));
+        let result = basin_hopping(&model, varmap, &path, config);
+        let manifest = Manifest::read(&path)?;
+
+        assert!(matches!(
+            result,
+            Err(Error::Config(builder::ConfigError::Temperature(t))) if t.is_nan()
+        ));
+        assert_eq!(manifest.next_step, 3);
+        assert!(manifest.result.history.iter().all(|h| h.temperature == 1.));
+        Ok(())
+    }
+
+    #[test]
+    fn errors_are_typed() -> anyhow::Result<()> {
+        let (model, varmap, file) = fixture::<Wells>("not_dir")?;
+        fs::write(&file, "")?;
+        let resumed = resume_basin_hopping(&model, varmap.clone(), &file, config(1));
+        let started = basin_hopping(&model, varmap, &file, config(1));
+
+        assert!(matches!(resumed, Err(Error::NotADirectory(p)) if p == *file));
+        assert!(matches!(started, Err(Error::NotADirectory(_))));
+        Ok(())
+    }
+
+    /// uniform steps, except the `nan_at`th steps (counting retries) set every weight to NaN
+    fn nan_step(nan_at: &[usize]) -> Box<dyn StepTaker> {
+        let nan_at = nan_at.to_vec();
+        let mut calls = 0;
+        Box::new(
+            move |vars: &[Var], step_size: f64, rng: &mut dyn rand::RngCore| {
+                calls += 1;
+                if nan_at.contains(&calls) {
+                    for v in vars {
+                        v.set(&v.affine(0., f64::NAN)?)?;
+                    }
+                    Ok(())
+                } else {
+                    UniformStep.take_step(vars, step_size, rng)
+                }
+            },
+        )
+    }
+
+    #[test]
+    fn non_finite_policies() -> anyhow::Result<()> {
+        let path = TempDir::new("nonfinite");
+        let run = |policy: NonFinitePolicy| -> Result<BhopResult> {
+            let (model, varmap) = new_model::<Wells>()?;
+            let mut config = config(14);
+            config.step_taker = nan_step(&[2]);
+            config.non_finite = policy;
+            basin_hopping(&model, varmap, &path, config)
+        };
+
+        let rejected = run(NonFinitePolicy::Reject)?;
+        let manifest = Manifest::read(&path)?;
+        let retried = run(NonFinitePolicy::Retry {
+            max_retries: 2,
+            factor: 0.5,
+        })?;
+        let aborted = run(NonFinitePolicy::Abort);
+
+        assert_eq!(rejected.n_hops(), 6);
+        assert_eq!(rejected.n_non_finite, 1);
+        assert_eq!(rejected.history[2].outcome, HopOutcome::NonFinite);
+        assert!(!rejected.history[2].retained);
+        assert!(rejected.min_loss.is_finite());
+        assert!(manifest.result.history[2].loss.is_nan());
+        assert_eq!(retried.n_non_finite, 1);
+        assert_ne!(retried.history[2].outcome, HopOutcome::NonFinite);
+        assert_eq!(retried.history[2].step_size, 0.5);
+        assert!(matches!(aborted, Err(Error::NonFiniteLoss { step: 2, .. })));
+        Ok(())
+    }
+
+    /// records the steps minimised, and the step and retry of each non-finite relaxation
+    #[derive(Default)]
+    struct Relaxations {
+        minimised: Rc<RefCell<Vec<usize>>>,
+        non_finite: Rc<RefCell<Vec<(usize, usize)>>>,
+    }
+
+    impl BhopObserver for Relaxations {
+        fn on_minimised(
+            &mut self,
+            step: usize,
+            _loss: f64,
+            _relaxation: &training::Relaxation,
+        ) -> ControlFlow<()> {
+            self.minimised.borrow_mut().push(step);
+            ControlFlow::Continue(())
+        }
+
+        fn on_non_finite(
+            &mut self,
+            step: usize,
+            loss: f64,
+            _relaxation: &training::Relaxation,
+            retries: usize,
+        ) -> ControlFlow<()> {
+            assert!(loss.is_nan());
+            self.non_finite.borrow_mut().push((step, retries));
+            ControlFlow::Continue(())
+        }
+    }
+
+    #[test]
+    fn observer_sees_every_relaxation() -> anyhow::Result<()> {
+        let (model, varmap, path) = fixture::<Wells>("observer_non_finite")?;
+        let relaxations = Relaxations::default();
+        let (minimised, non_finite) = (
+            relaxations.minimised.clone(),
+            relaxations.non_finite.clone(),
+        );
+        let mut config = config(14);
+        config.observer = Box::new(relaxations);
+        // the first step is not perturbed: step 2 fails twice before its second retry
+        // succeeds, and step 4 fails every try
+        config.step_taker = nan_step(&[2, 3, 6, 7, 8]);
+        config.non_finite = NonFinitePolicy::Retry {
+            max_retries: 2,
+            factor: 0.5,
+        };
+        let result = basin_hopping(&model, varmap, &path, config)?;
+
+        assert_eq!(*minimised.borrow(), [0, 1, 2, 3, 5]);
+        assert_eq!(
+            *non_finite.borrow(),
+            [(2, 0), (2, 1), (4, 0), (4, 1), (4, 2)]
+        );
+        assert_eq!(result.n_non_finite, 5);
+        assert_eq!(result.history[4].outcome, HopOutcome::NonFinite);
+        Ok(())
+    }
+
+    #[test]
+    fn step_scale_matches_smaller_step_size() -> anyhow::Result<()> {
+        use crate::step::StepScale;
+
+        // scaling the only variable is the same as scaling the step size
+        let mut scaled = config(8);
+        scaled.step_scale = Some(StepScale::Prefix {
+            scales: vec![("w".into(), 0.5)],
+            default: 1.,
+        });
+        let mut smaller = config(8);
+        smaller.step_size = 0.5;
+        assert_eq!(
+            losses_with(scaled, "step_scale")?,
+            losses_with(smaller, "step_size")?
+        );
+        Ok(())
+    }
+
+    /// Two sets of wells, to freeze one of them
+    #[derive(Clone)]
+    struct Pair {
+        a: Wells,
+        b: Wells,
+    }
+
+    impl SimpleModel for Pair {
+        type SetupVars = ();
+
+        fn new(vs: VarBuilder, _setup_vars: ()) -> candle_core::Result<Self> {
+            let a = Wells::new(vs.pp("a"), ())?;
+            let b = Wells::new(vs.pp("b"), ())?;
+            Ok(Self { a, b })
+        }
+
+        fn test_eval(&self) -> candle_core::Result<f32> {
+            self.loss()?.to_dtype(DType::F32)?.to_scalar::<f32>()
+        }
+    }
+
+    impl Model for Pair {
+        fn loss(&self) -> candle_core::Result<Tensor> {
+            self.a.loss()? + self.b.loss()?
+        }
+    }
+
+    #[test]
+    fn mask_freezes_unselected_vars() -> anyhow::Result<()> {
+        use crate::mask::VarMask;
+
+        let (model, varmap, path) = fixture::<Pair>("mask")?;
+        let mut frozen = config(9);
+        frozen.mask = Some(VarMask::only(["a.*"]));
+        frozen.l2_reg = Some(0.1);
+        let result = basin_hopping(&model, varmap.clone(), &path, frozen)?;
+        let mut none = config(9);
+        none.mask = Some(VarMask::except(["*"]));
+        let empty_path = TempDir::new("mask_empty");
+        let empty = basin_hopping(&model, varmap.clone(), &empty_path, none);
+
+        let data = varmap.data().lock().unwrap();
+        let values = |name: &str| data[name].as_tensor().to_vec1::<f64>();
+        assert!(values("a.w")?.iter().any(|&w| w != 0.5));
+        assert!(values("b.w")?.iter().all(|&w| w == 0.5));
+        // the L2 term leaves out the frozen variables
+        let current = result
+            .history
+            .iter()
+            .rfind(|h| h.outcome.accepted())
+            .unwrap();
+        let l2: f64 = values("a.w")?.iter().map(|w| w * w).sum();
+        assert!((current.l2 - 0.1 * l2).abs() < 1e-9);
+        assert!(matches!(empty, Err(Error::NoVariables)));
+        assert!(!empty_path.exists());
+        Ok(())
+    }
+
+    /// Wells with a test metric of the mean weight, which disagrees with the loss
+    #[derive(Clone)]
+    struct MeanMetric(Wells);
+
+    impl SimpleModel for MeanMetric {
+        type SetupVars = ();
+
+        fn new(vs: VarBuilder, _setup_vars: ()) -> candle_core::Result<Self> {
+            Wells::new(vs, ()).map(Self)
+        }
+
+        fn test_eval(&self) -> candle_core::Result<f32> {
+            self.0
+                .w
+                .mean_all()?
+                .to_dtype(DType::F32)?
+                .to_scalar::<f32>()
+        }
+    }
+
+    impl Model for MeanMetric {
+        fn loss(&self) -> candle_core::Result<Tensor> {
+            self.0.loss()
+        }
+    }
+
+    #[test]
+    fn best_by_test_metric() -> anyhow::Result<()> {
+        let (model, varmap, path) = fixture::<MeanMetric>("best_by")?;
+        let mut config = config(6);
+        config.steps = 8;
+        config.best_by = BestBy::TestMetric {
+            higher_is_better: true,
+        };
+        config.retention = Retention::CurrentAndBest;
+        config.restore = Restore::Memory;
+        let result = basin_hopping(&model, varmap, &path, config)?;
+        let manifest = Manifest::read(&path)?;
+
+        let metrics: Vec<_> = result.history.iter().map(|h| h.test_metric).collect();
+        assert!(metrics.iter().all(Option::is_some));
+        let best = result.best().unwrap();
+        let max = metrics
+            .iter()
+            .flatten()
+            .fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+        assert_eq!(best.test_metric, Some(max));
+        assert!(best.retained);
+        assert!(result.retained_names().any(|n| n == result.min_name));
+        assert_eq!(manifest.result.best(), Some(best));
+        Ok(())
+    }
+
+    impl candle_nn::Module for Wells {
+        fn forward(&self, xs: &Tensor) -> candle_core::Result<Tensor> {
+            xs.broadcast_mul(&self.w)
+        }
+    }
+
+    #[test]
+    fn ensemble_averages_members() -> anyhow::Result<()> {
+        use crate::ensemble::{Ensemble, Members};
+
+        let (model, varmap, path) = fixture::<Wells>("ensemble")?;
+        let result = basin_hopping(&model, varmap, &path, config(10))?;
+        let load = |members| {
+            Ensemble::<Wells>::load(&result, &path, members, &(), DType::F64, &Device::Cpu)
+        };
+        let top = load(Members::TopK(3))?;
+        let accepted = load(Members::Accepted)?;
+        let boltzmann = load(Members::Boltzmann { temperature: 1. })?;
+        let invalid = load(Members::Boltzmann { temperature: 0. });
+
+        assert_eq!(top.len(), 3);
+        assert_eq!(top.members[0].name, result.min_name);
+        assert!(top.members.windows(2).all(|m| m[0].loss <= m[1].loss));
+        let xs = Tensor::ones(8, DType::F64, &Device::Cpu)?;
+        let outputs = top.member_predictions(&xs)?;
+        let mut mean = vec![0.; 8];
+        for output in &outputs {
+            for (m, o) in mean.iter_mut().zip(output.to_vec1::<f64>()?) {
+                *m += o / 3.;
+            }
+        }
+        for (p, m) in top.predict(&xs)?.to_vec1::<f64>()?.iter().zip(mean) {
+            assert!((p - m).abs() < 1e-12);
+        }
+        let probs = top.predict_probs(&xs)?.sum_all()?.to_scalar::<f64>()?;
+        assert!((probs - 1.).abs() < 1e-12);
+
+        assert_eq!(accepted.len(), result.n_accepted);
+        let weights: Vec<_> = boltzmann.members.iter().map(|m| m.weight).collect();
+        assert!((weights.iter().sum::<f64>() - 1.).abs() < 1e-12);
+        assert!(weights.windows(2).all(|w| w[0] >= w[1]));
+        assert!(matches!(invalid, Err(Error::Config(_))));
+        let empty = Ensemble::<Wells> {
+            members: Vec::new(),
+        };
+        assert!(matches!(empty.predict(&xs), Err(Error::NoMembers)));
+        assert!(matches!(empty.predict_probs(&xs), Err(Error::NoMembers)));
+        Ok(())
+    }
+
+    #[test]
+    fn compare_minima_pairwise() -> anyhow::Result<()> {
+        use crate::compare::compare_minima;
+
+        let (model, varmap, path) = fixture::<Wells>("compare")?;
+        let result = basin_hopping(&model, varmap, &path, config(12))?;
+        let names: Vec<_> = result.names().collect();
+        let inputs = Tensor::randn(0_f64, 1., (5, 8), &Device::Cpu)?;
+        let comparison =
+            compare_minima::<Wells, _, _>(&names, &path, &inputs, (), DType::F64, &Device::Cpu)?;
+        let a = candle_core::safetensors::load(path.join(names[0]), &Device::Cpu)?;
+        let b = candle_core::safetensors::load(path.join(names[1]), &Device::Cpu)?;
+
+        assert_eq!(comparison.len(), result.n_hops());
+        for (i, hop) in result.history.iter().enumerate() {
+            assert!((comparison.losses[i] - hop.loss).abs() < 1e-9);
+            assert_eq!(comparison.agreement[i][i], 1.);
+            assert_eq!(comparison.distance[i][i], 0.);
+            for j in 0..comparison.len() {
+                assert_eq!(comparison.agreement[i][j], comparison.agreement[j][i]);
+                assert_eq!(comparison.distance[i][j], comparison.distance[j][i]);
+                assert_eq!(
+                    comparison.loss_difference[i][j],
+                    -comparison.loss_difference[j][i]
+                );
+            }
+        }
+        let distance = (&a["w"] - &b["w"])?
+            .sqr()?
+            .sum_all()?
+            .sqrt()?
+            .to_scalar::<f64>()?;
+        assert!((comparison.distance[0][1] - distance).abs() < 1e-12);
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_minima_share_ids() -> anyhow::Result<()> {
+        let duplicates = |seed| {
+            let mut config = config(seed);
+            config.steps = 10;
+            config.step_size = 0.2;
+            config.duplicates = Some(DuplicateDetection::default());
+            config
+        };
+        let run = |config: BhopConfig, path: &Path, resume: bool| -> Result<BhopResult> {
+            let (model, varmap) = new_model::<Wells>()?;
+            if resume {
+                resume_basin_hopping(&model, varmap, path, config)
+            } else {
+                basin_hopping(&model, varmap, path, config)
+            }
+        };
+
+        let full = run(duplicates(4), &TempDir::new("duplicates_full"), false)?;
+        let path = TempDir::new("duplicates_resume");
+        let mut first = duplicates(4);
+        first.steps = 5;
+        run(first, &path, false)?;
+        let resumed = run(duplicates(4), &path, true)?;
+
+        let ids: Vec<_> = full.history.iter().map(|h| h.minimum).collect();
+        assert!(ids.iter().all(Option::is_some));
+        assert_eq!(ids[0], Some(0));
+        for a in &full.history {
+            for b in &full.history {
+                if a.minimum == b.minimum {
+                    assert!((a.loss - b.loss).abs() <= 1e-6);
+                }
+            }
+        }
+        assert!(full.n_unique_minima() > 1);
+        assert!(full.n_unique_minima() < full.n_hops());
+        let resumed_ids: Vec<_> = resumed.history.iter().map(|h| h.minimum).collect();
+        assert_eq!(ids, resumed_ids);
+        Ok(())
+    }
 
     #[test]
     fn it_works() {
         // let result = add(2, 2);
         assert_eq!(4, 4);
     }
+
+    #[test]
+    fn same_seed_same_losses() -> anyhow::Result<()> {
+        let a = loss_sequence(7, "seed_a")?;
+        let b = loss_sequence(7, "seed_b")?;
+        assert_eq!(a, b);
+        // the hops should actually go somewhere
+        assert!(a.windows(2).any(|w| w[0] != w[1]));
+        Ok(())
+    }
 }
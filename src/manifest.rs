@@ -0,0 +1,180 @@
+/*!
+The run manifest, a JSON record of a basin hopping run kept in its output directory
+
+The manifest is rewritten after every step, and records the configuration, every step taken
+and the current and best checkpoints. It allows an interrupted run to be resumed, and
+downstream tools to find the checkpoints of interest without parsing the logs.
+*/
+
+use std::{
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rand::SeedableRng;
+use rand_xoshiro::Xoshiro256StarStar;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    checkpoint::{Restore, Retention},
+    duplicates::DuplicateDetection,
+    mask::VarMask,
+    nonfinite::NonFinitePolicy,
+    step::{AdaptiveStepSize, StepScale},
+    BestBy, BhopConfig, BhopResult, HopRecord, Result,
+};
+
+/// The name of the manifest file within the output directory
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// The settings of a basin hopping run, as recorded in the manifest
+///
+/// The temperature schedule, stop criteria and the optimiser enums from candle-optimisers are
+/// recorded in their debug representation
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManifestConfig {
+    /// the number of basin hopping steps
+    pub steps: usize,
+    /// the temperature schedule for the MC criterion
+    pub temperature: String,
+    /// The initial size of each basin hopping step
+    pub step_size: f64,
+    /// The scaling of the step size of each variable
+    #[serde(default)]
+    pub step_scale: Option<StepScale>,
+    /// The variables perturbed and relaxed
+    #[serde(default)]
+    pub mask: Option<VarMask>,
+    /// How the best step is chosen
+    #[serde(default)]
+    pub best_by: BestBy,
+    /// The adaptive step size settings
+    pub adaptive_step: Option<AdaptiveStepSize>,
+    /// The number of lbfgs steps
+    pub lbfgs_steps: usize,
+    /// The step convergence criterion
+    pub step_conv: String,
+    /// The gradient convergence criterion
+    pub grad_conv: String,
+    /// The history size for the lbfgs optimiser
+    pub history_size: usize,
+    /// The L2 regularisation factor
+    pub l2_reg: Option<f64>,
+    /// the line search method
+    pub linesearch: Option<String>,
+    /// The random seed
+    pub seed: u64,
+    /// Which checkpoints are kept on disk
+    pub retention: Retention,
+    /// Where the current minimum is restored from on rejection
+    pub restore: Restore,
+    /// The criteria for stopping early, in their debug representation
+    #[serde(default)]
+    pub stop: String,
+    /// What is done when a relaxation ends with a non-finite loss or weights
+    #[serde(default)]
+    pub non_finite: NonFinitePolicy,
+    /// The tolerances for recognising revisited minima, if detecting them
+    #[serde(default)]
+    pub duplicates: Option<DuplicateDetection>,
+}
+
+impl From<&BhopConfig> for ManifestConfig {
+    fn from(config: &BhopConfig) -> Self {
+        Self {
+            steps: config.steps,
+            temperature: format!("{:?}", config.temperature),
+            step_size: config.step_size,
+            step_scale: config.step_scale.clone(),
+            mask: config.mask.clone(),
+            best_by: config.best_by,
+            adaptive_step: config.adaptive_step,
+            lbfgs_steps: config.lbfgs_steps,
+            step_conv: format!("{:?}", config.step_conv),
+            grad_conv: format!("{:?}", config.grad_conv),
+            history_size: config.history_size,
+            l2_reg: config.l2_reg,
+            linesearch: config.linesearch.map(|l| format!("{l:?}")),
+            seed: config.seed,
+            retention: config.retention,
+            restore: config.restore,
+            stop: format!("{:?}", config.stop),
+            non_finite: config.non_finite,
+            duplicates: config.duplicates,
+        }
+    }
+}
+
+/// The state of a basin hopping run, rewritten to [`MANIFEST_NAME`] after every step
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The settings of the run (of the latest session, if the run was resumed)
+    pub config: ManifestConfig,
+    /// When the run was started, in seconds since the unix epoch
+    pub started_at: u64,
+    /// When the manifest was last written, in seconds since the unix epoch
+    pub updated_at: u64,
+    /// The index of the next step to take
+    pub next_step: usize,
+    /// The loss of the current minimum
+    pub current_loss: f64,
+    /// The checkpoint holding the current minimum
+    pub current_name: String,
+    /// The random number generator, as it was after the last step
+    pub rng: Xoshiro256StarStar,
+    /// The results so far, including the best checkpoint and the record of every step
+    pub result: BhopResult,
+}
+
+impl Manifest {
+    pub(crate) fn new(config: &BhopConfig) -> Self {
+        let now = timestamp();
+        Self {
+            config: config.into(),
+            started_at: now,
+            updated_at: now,
+            next_step: 0,
+            current_loss: f64::INFINITY,
+            current_name: String::new(),
+            rng: Xoshiro256StarStar::seed_from_u64(config.seed),
+            result: BhopResult::new(config.step_size, config.best_by),
+        }
+    }
+
+    /// Read the manifest from the output directory of a run
+    pub fn read<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let file = fs::File::open(dir.as_ref().join(MANIFEST_NAME))?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    /// Write the manifest to the output directory
+    ///
+    /// The manifest is written to a temporary file first and then renamed,
+    /// so a run killed mid write leaves the previous manifest intact
+    pub(crate) fn write(&mut self, dir: &Path) -> Result<()> {
+        self.updated_at = timestamp();
+        let tmp = dir.join(format!("{MANIFEST_NAME}.tmp"));
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(tmp, dir.join(MANIFEST_NAME))?;
+        Ok(())
+    }
+
+    /// The name of the checkpoint holding the lowest loss
+    #[must_use]
+    pub fn best_name(&self) -> &str {
+        &self.result.min_name
+    }
+
+    /// The records of the accepted steps
+    pub fn accepted(&self) -> impl Iterator<Item = &HopRecord> {
+        self.result.history.iter().filter(|h| h.outcome.accepted())
+    }
+}
+
+/// The current time in seconds since the unix epoch
+pub(crate) fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
@@ -0,0 +1,131 @@
+/*!
+Selecting which variables are perturbed and relaxed, by name
+
+Variables are selected by patterns matched against their names in the [`VarMap`], where `*`
+matches any run of characters and `?` any single character, so `ln*` selects every linear
+layer of the alzheimers CNN and `conv1.*` only the first convolution. The variables left out
+keep their values for the whole run, and are left out of the L2 term of the loss, as the
+local minimiser only regularises the variables it relaxes.
+*/
+
+use candle_core::Var;
+use candle_nn::VarMap;
+use serde::{Deserialize, Serialize};
+
+use crate::training::sorted_named_vars;
+use crate::{Error, Result};
+
+/// Which variables to perturb and relax
+///
+/// A variable is selected if its name matches one of the `include` patterns (or `include` is
+/// empty) and none of the `exclude` patterns
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VarMask {
+    /// Patterns of the names to select, or every variable if empty
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Patterns of the names to leave out, even if included
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl VarMask {
+    /// Select only the variables matching one of `patterns`
+    pub fn only<S: Into<String>>(patterns: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            include: patterns.into_iter().map(Into::into).collect(),
+            exclude: Vec::new(),
+        }
+    }
+
+    /// Select every variable except those matching one of `patterns`
+    pub fn except<S: Into<String>>(patterns: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: patterns.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Whether the variable `name` is selected
+    #[must_use]
+    pub fn selects(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| matches(p, name)))
+            && !self.exclude.iter().any(|p| matches(p, name))
+    }
+}
+
+/// The variables of `varmap` selected by `mask`, with their names, in name order
+pub(crate) fn masked_vars(varmap: &VarMap, mask: Option<&VarMask>) -> Vec<(String, Var)> {
+    let mut vars = sorted_named_vars(varmap);
+    if let Some(mask) = mask {
+        vars.retain(|(name, _)| mask.selects(name));
+    }
+    vars
+}
+
+/// Check that `mask` selects at least one variable of `varmap`, before anything is written
+pub(crate) fn check_mask(varmap: &VarMap, mask: Option<&VarMask>) -> Result<()> {
+    if masked_vars(varmap, mask).is_empty() {
+        Err(Error::NoVariables)
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether `name` matches the wildcard `pattern`
+fn matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<_>, Vec<_>) = (pattern.chars().collect(), name.chars().collect());
+    // position after the last `*`, and the name position it was matched up to
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p + 1, n));
+            p += 1;
+        } else if let Some((after, matched)) = star {
+            // let the last `*` take one more character
+            p = after;
+            n = matched + 1;
+            star = Some((after, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_whole_names() {
+        assert!(matches("conv1.*", "conv1.weight"));
+        assert!(matches("ln*", "ln"));
+        assert!(matches("*.bias", "ln1.bias"));
+        assert!(matches("a*b*c", "axxbyyc"));
+        assert!(matches("c?", "cx"));
+        assert!(!matches("c?", "cxy"));
+        assert!(!matches("conv1.*", "conv2.weight"));
+        assert!(!matches("*.bias", "ln1.bias2"));
+    }
+
+    #[test]
+    fn mask_selects_included_and_not_excluded() {
+        let mask = VarMask {
+            include: vec!["a.*".into(), "c?".into()],
+            exclude: vec!["*.bias".into()],
+        };
+        assert!(mask.selects("a.w"));
+        assert!(mask.selects("cx"));
+        assert!(!mask.selects("a.bias"));
+        assert!(!mask.selects("b.w"));
+        assert!(!mask.selects("cxy"));
+        assert!(VarMask::except(["b.*"]).selects("a.w"));
+        assert!(!VarMask::except(["*"]).selects("a.w"));
+        assert!(VarMask::default().selects("anything"));
+    }
+}
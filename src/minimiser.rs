@@ -0,0 +1,165 @@
+/*!
+Local minimisers, used to relax the model into the basin it was perturbed into
+
+[`basin_hopping`](crate::basin_hopping) uses L-BFGS with the settings from the [`BhopConfig`].
+For models where full batch L-BFGS is too expensive, a first order optimiser can be passed to
+[`basin_hopping_with`](crate::basin_hopping_with) instead, and models whose training set is too
+large to evaluate at once can be relaxed batch by batch by wrapping the minimiser in [`Batched`].
+*/
+
+use candle_core::Var;
+use candle_nn::Optimizer;
+use optimisers::{
+    adam::{Adam, ParamsAdam},
+    esgd::{ParamsSGD, SGD},
+    lbfgs::ParamsLBFGS,
+};
+
+use crate::observer::BhopObserver;
+use crate::training::{
+    batched_gradients, full_batch_gradients, run_first_order_training, run_lbfgs_training,
+    Relaxation,
+};
+use crate::{BatchedModel, BhopConfig, Result, SimpleModel};
+
+/// A local optimiser, relaxing the variables of a model to the bottom of their basin
+pub trait LocalMinimiser<M: SimpleModel> {
+    /// Minimise the loss of `model` with respect to `vars`, updating them in place
+    ///
+    /// `vars` are always in the same order (sorted by name), so a deterministic minimiser
+    /// gives reproducible runs. [`BhopObserver::on_lbfgs_step`] should be called after each
+    /// iteration, stopping the minimisation if it breaks.
+    fn minimise(
+        &mut self,
+        model: &M,
+        vars: Vec<Var>,
+        observer: &mut dyn BhopObserver,
+    ) -> Result<Relaxation>;
+}
+
+/// L-BFGS, the default minimiser
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LbfgsMinimiser {
+    /// The L-BFGS settings
+    pub params: ParamsLBFGS,
+    /// The maximum number of L-BFGS steps
+    pub steps: usize,
+}
+
+impl From<&BhopConfig> for LbfgsMinimiser {
+    fn from(config: &BhopConfig) -> Self {
+        Self {
+            params: ParamsLBFGS {
+                lr: 1.,
+                history_size: config.history_size,
+                line_search: config.linesearch,
+                step_conv: config.step_conv,
+                grad_conv: config.grad_conv,
+                weight_decay: config.l2_reg.map(|x| 2. * x),
+            },
+            steps: config.lbfgs_steps,
+        }
+    }
+}
+
+impl<M: SimpleModel> LocalMinimiser<M> for LbfgsMinimiser {
+    fn minimise(
+        &mut self,
+        model: &M,
+        vars: Vec<Var>,
+        observer: &mut dyn BhopObserver,
+    ) -> Result<Relaxation> {
+        run_lbfgs_training(model, vars, self.params, self.steps, observer)
+    }
+}
+
+/// Adam, stopping once the loss changes by less than `loss_tol` in a step
+///
+/// The L2 term of [`BhopConfig::l2_reg`] is only added to the reported loss: to minimise it
+/// set the weight decay of `params` to match
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdamMinimiser {
+    /// The Adam settings
+    pub params: ParamsAdam,
+    /// The maximum number of Adam steps
+    pub steps: usize,
+    /// Convergence threshold on the change in loss over one step
+    pub loss_tol: f64,
+}
+
+impl<M: SimpleModel> LocalMinimiser<M> for AdamMinimiser {
+    fn minimise(
+        &mut self,
+        model: &M,
+        vars: Vec<Var>,
+        observer: &mut dyn BhopObserver,
+    ) -> Result<Relaxation> {
+        let optimiser = Adam::new(vars, self.params.clone())?;
+        run_first_order_training(optimiser, self.steps, self.loss_tol, observer, || {
+            full_batch_gradients(model)
+        })
+    }
+}
+
+/// Stochastic gradient descent, stopping once the loss changes by less than `loss_tol` in a step
+///
+/// As for [`AdamMinimiser`], any L2 regularisation is set through the weight decay of `params`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SgdMinimiser {
+    /// The SGD settings
+    pub params: ParamsSGD,
+    /// The maximum number of SGD steps
+    pub steps: usize,
+    /// Convergence threshold on the change in loss over one step
+    pub loss_tol: f64,
+}
+
+impl<M: SimpleModel> LocalMinimiser<M> for SgdMinimiser {
+    fn minimise(
+        &mut self,
+        model: &M,
+        vars: Vec<Var>,
+        observer: &mut dyn BhopObserver,
+    ) -> Result<Relaxation> {
+        let optimiser = SGD::new(vars, self.params.clone())?;
+        run_first_order_training(optimiser, self.steps, self.loss_tol, observer, || {
+            full_batch_gradients(model)
+        })
+    }
+}
+
+/// Relax a [`BatchedModel`] with a first order minimiser, one batch at a time
+///
+/// Each step of the minimiser uses the loss and gradients summed over every batch,
+/// so only a single batch need be held in memory. L-BFGS needs the full batch loss
+/// for its line search, so only [`AdamMinimiser`] and [`SgdMinimiser`] can be batched.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Batched<T>(pub T);
+
+impl<M: BatchedModel> LocalMinimiser<M> for Batched<AdamMinimiser> {
+    fn minimise(
+        &mut self,
+        model: &M,
+        vars: Vec<Var>,
+        observer: &mut dyn BhopObserver,
+    ) -> Result<Relaxation> {
+        let optimiser = Adam::new(vars.clone(), self.0.params.clone())?;
+        run_first_order_training(optimiser, self.0.steps, self.0.loss_tol, observer, || {
+            batched_gradients(model, &vars)
+        })
+    }
+}
+
+impl<M: BatchedModel> LocalMinimiser<M> for Batched<SgdMinimiser> {
+    fn minimise(
+        &mut self,
+        model: &M,
+        vars: Vec<Var>,
+        observer: &mut dyn BhopObserver,
+    ) -> Result<Relaxation> {
+        let optimiser = SGD::new(vars.clone(), self.0.params.clone())?;
+        run_first_order_training(optimiser, self.0.steps, self.0.loss_tol, observer, || {
+            batched_gradients(model, &vars)
+        })
+    }
+}
@@ -0,0 +1,195 @@
+/*!
+Multi-start basin hopping, running independent walkers concurrently on CPU threads
+
+Each walker builds its own model and variables with [`SimpleModel::new`], so starts from its own
+initialisation, and uses its own seed. The walkers share nothing while running, and their
+minima are merged once they have all finished.
+
+The seeds only drive the hops: random initialisations are drawn from the global random number
+generator of the device, which candle cannot seed on the CPU, so a run is only reproducible if
+[`SimpleModel::new`] initialises the weights deterministically, for example by loading them.
+*/
+
+use std::{
+    fs,
+    num::NonZeroUsize,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use candle_core::{DType, Device};
+use candle_nn::{VarBuilder, VarMap};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{basin_hopping, BestBy, BhopConfig, BhopResult, Error, Result, SimpleModel};
+
+/// The name of the file the combined result is written to, in the top level output directory
+pub const MULTI_START_NAME: &str = "multistart.json";
+
+/// Settings for a multi-start run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultiStartConfig {
+    /// The number of independent walkers
+    pub walkers: usize,
+    /// The number of threads to run them on, or one per available core if not set
+    pub threads: Option<usize>,
+}
+
+/// The result of a multi-start run
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MultiStartResult {
+    /// The result of each walker
+    pub walkers: Vec<BhopResult>,
+    /// The steps of every walker merged into one result
+    ///
+    /// Checkpoint names are relative to the top level output directory, and the step size
+    /// and stop reason are those of the walker which found the lowest loss. Minimum IDs are
+    /// offset so those of each walker stay distinct, as minima are not matched across walkers
+    pub combined: BhopResult,
+}
+
+impl MultiStartResult {
+    fn new(walkers: Vec<BhopResult>) -> Self {
+        let mut combined = BhopResult::new(
+            walkers.first().map_or(0., |r| r.step_size),
+            walkers.first().map_or(BestBy::Loss, |r| r.best_by),
+        );
+        let mut id_offset = 0;
+        for (k, result) in walkers.iter().enumerate() {
+            let dir = walker_dir(k);
+            let n_ids = result
+                .history
+                .iter()
+                .filter_map(|h| h.minimum)
+                .max()
+                .map_or(0, |id| id + 1);
+            if result.min_loss < combined.min_loss {
+                combined.min_loss = result.min_loss;
+                combined.min_name = format!("{}/{}", dir, result.min_name);
+                combined.step_size = result.step_size;
+                combined.stop_reason = result.stop_reason;
+            }
+            combined.n_accepted += result.n_accepted;
+            combined.n_non_finite += result.n_non_finite;
+            combined
+                .history
+                .extend(result.history.iter().cloned().map(|mut hop| {
+                    hop.name = format!("{}/{}", dir, hop.name);
+                    hop.minimum = hop.minimum.map(|id| id + id_offset);
+                    hop
+                }));
+            id_offset += n_ids;
+        }
+        Self { walkers, combined }
+    }
+
+    /// The index and result of the walker with the best step, by
+    /// [`BhopResult::best_by`]: the lowest loss by default
+    #[must_use]
+    pub fn best(&self) -> Option<(usize, &BhopResult)> {
+        self.walkers
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.best_score().total_cmp(&b.best_score()))
+    }
+
+    /// Read the combined result from the output directory of a multi-start run
+    pub fn read<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let file = fs::File::open(dir.as_ref().join(MULTI_START_NAME))?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+}
+
+/// The output directory of walker `k`, relative to the top level output directory
+fn walker_dir(k: usize) -> String {
+    format!("walker_{k:03}")
+}
+
+/// Run independent basin hopping walkers concurrently
+///
+/// Walker `k` builds its model from `setup_vars` with variables of `dtype` on `device`, and
+/// runs [`basin_hopping`] with the config returned by `config`, but with the seed `seed + k`
+/// for its steps and Metropolis criterion (the initial weights are not seeded). It writes its
+/// checkpoints and manifest to `path/walker_00k`, so it can be continued on its own with
+/// [`resume_basin_hopping`](crate::resume_basin_hopping). Once all the walkers have finished,
+/// their results are merged and written to [`MULTI_START_NAME`] in `path`.
+pub fn multi_start<M, P, F>(
+    setup_vars: &M::SetupVars,
+    dtype: DType,
+    device: &Device,
+    path: P,
+    config: F,
+    multi_start: MultiStartConfig,
+) -> Result<MultiStartResult>
+where
+    M: SimpleModel,
+    M::SetupVars: Clone + Sync,
+    P: AsRef<Path>,
+    F: Fn() -> BhopConfig + Sync,
+{
+    let path: &Path = path.as_ref();
+    let n_walkers = multi_start.walkers;
+    if n_walkers == 0 {
+        return Err(Error::NoWalkers);
+    }
+    config().validate()?;
+    // created by the first walker to start, once it has checked its variables
+    if path.exists() && !path.is_dir() {
+        return Err(Error::NotADirectory(path.to_path_buf()));
+    }
+    let n_threads = multi_start
+        .threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get))
+        .clamp(1, n_walkers);
+    info!("running {} walkers on {} threads", n_walkers, n_threads);
+
+    let run_walker = |k: usize| -> Result<BhopResult> {
+        let mut config = config();
+        config.seed = config.seed.wrapping_add(k as u64);
+        let varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&varmap, dtype, device);
+        let model = M::new(vs, setup_vars.clone())?;
+        let result = basin_hopping(&model, varmap, path.join(walker_dir(k)), config)?;
+        info!("walker {} finished, min loss {}", k, result.min_loss);
+        Ok(result)
+    };
+
+    // each thread takes the next walker until there are none left
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Result<BhopResult>)> = thread::scope(|s| {
+        let handles: Vec<_> = (0..n_threads)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let k = next.fetch_add(1, Ordering::Relaxed);
+                        if k >= n_walkers {
+                            break done;
+                        }
+                        done.push((k, run_walker(k)));
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    });
+    results.sort_unstable_by_key(|(k, _)| *k);
+    let walkers = results
+        .into_iter()
+        .map(|(_, r)| r)
+        .collect::<Result<Vec<_>>>()?;
+
+    let result = MultiStartResult::new(walkers);
+    info!("final min loss: {}", result.combined.min_loss);
+    info!("final min name: {}", result.combined.min_name);
+    fs::write(
+        path.join(MULTI_START_NAME),
+        serde_json::to_vec_pretty(&result)?,
+    )?;
+    Ok(result)
+}
@@ -0,0 +1,45 @@
+/*!
+Handling of relaxations that diverge to a non-finite loss or weights
+*/
+
+use candle_core::{DType, Var};
+use serde::{Deserialize, Serialize};
+
+/// What to do when the loss or the weights at a minimum are NaN or infinite
+///
+/// The weights of a non-finite minimum are never saved, and every occurrence is counted in
+/// [`BhopResult::n_non_finite`](crate::BhopResult::n_non_finite). The first step has no
+/// minimum to fall back on, so always aborts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum NonFinitePolicy {
+    /// Reject the step and restore the current minimum
+    #[default]
+    Reject,
+    /// Restore the current minimum and retry the step, multiplying the step size by `factor`
+    /// each time, rejecting the step after `max_retries` failed retries
+    Retry {
+        /// The maximum number of retries of a step
+        max_retries: usize,
+        /// The factor the step size is multiplied by for each retry, in (0, 1)
+        factor: f64,
+    },
+    /// Stop the run with [`Error::NonFiniteLoss`](crate::Error::NonFiniteLoss)
+    Abort,
+}
+
+/// Whether every element of the variables is finite
+///
+/// `0 * x` is zero for finite `x` and NaN otherwise, so the sum is NaN if any element is not
+/// finite, without overflowing
+pub(crate) fn all_finite(vars: &[Var]) -> candle_core::Result<bool> {
+    for v in vars {
+        let sum = (v.as_tensor() * 0.)?
+            .sum_all()?
+            .to_dtype(DType::F64)?
+            .to_scalar::<f64>()?;
+        if sum.is_nan() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
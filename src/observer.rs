@@ -0,0 +1,70 @@
+/*!
+Observer hooks, called at each event of a basin hopping run
+
+Every hook returns a [`ControlFlow`]: returning [`ControlFlow::Break`] stops the run once the
+current step has been recorded in the manifest, so it can later be resumed. Breaking from
+[`BhopObserver::on_lbfgs_step`] also ends the local minimisation early.
+*/
+
+use std::ops::ControlFlow;
+
+use candle_nn::VarMap;
+
+use crate::training::Relaxation;
+use crate::HopRecord;
+
+/// Callbacks for the events of a basin hopping run, all of which do nothing by default
+#[allow(unused_variables)]
+pub trait BhopObserver {
+    /// Called before the variables are perturbed for step `step`
+    fn on_hop_start(&mut self, step: usize, temperature: f64) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// Called after each iteration of the local minimiser, whichever minimiser is used
+    fn on_lbfgs_step(&mut self, iteration: usize, loss: f64) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// Called once the local minimisation of step `step` has finished, before the Metropolis
+    /// criterion is applied, with the loss including the L2 term
+    ///
+    /// Only called for finite minima: relaxations ending with a non-finite loss or weights
+    /// are reported to [`BhopObserver::on_non_finite`] instead
+    fn on_minimised(&mut self, step: usize, loss: f64, relaxation: &Relaxation) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// Called for every relaxation of step `step` ending with a non-finite loss or weights,
+    /// including each retry, before the policy for non-finite relaxations is applied
+    ///
+    /// `retries` is the number of retries of the step so far. Breaking also stops any
+    /// further retries of the step.
+    fn on_non_finite(
+        &mut self,
+        step: usize,
+        loss: f64,
+        relaxation: &Relaxation,
+        retries: usize,
+    ) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// Called when a step is accepted, with the variables holding the new current minimum
+    fn on_accept(&mut self, record: &HopRecord, varmap: &VarMap) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// Called when a step is rejected, after the variables are reset to the current minimum
+    fn on_reject(&mut self, record: &HopRecord) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// Called when a step finds a new global minimum, after [`BhopObserver::on_accept`]
+    fn on_new_global_min(&mut self, record: &HopRecord, varmap: &VarMap) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+/// No observer
+impl BhopObserver for () {}
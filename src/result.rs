@@ -0,0 +1,286 @@
+/*!
+Results of a basin hopping run
+*/
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// The outcome of the Metropolis criterion for a single basin hopping step
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HopOutcome {
+    /// A new global minimum: always accepted
+    NewGlobalMin,
+    /// Lower than the current minimum: always accepted
+    Decrease,
+    /// Higher than the current minimum, but accepted by the Metropolis criterion
+    AcceptedMetropolis,
+    /// Rejected by the Metropolis criterion
+    Rejected,
+    /// Rejected because the loss or weights were not finite after relaxation
+    NonFinite,
+}
+
+impl HopOutcome {
+    /// Whether the step was accepted
+    #[must_use]
+    pub fn accepted(self) -> bool {
+        !matches!(self, Self::Rejected | Self::NonFinite)
+    }
+}
+
+/// Why a basin hopping run stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StopReason {
+    /// Every step was taken
+    Completed,
+    /// Too many steps without a new global minimum
+    NoImprovement,
+    /// The lowest loss went below the target
+    TargetLoss,
+    /// The time limit was reached
+    TimeLimit,
+    /// The user predicate was met
+    Predicate,
+    /// An observer stopped the run
+    Observer,
+}
+
+/// How the best step of a run is chosen
+///
+/// The Metropolis criterion and the global minimum always use the training loss; this only
+/// changes which step [`BhopResult::best`] reports, and keeps its checkpoint on disk
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BestBy {
+    /// The lowest training loss
+    #[default]
+    Loss,
+    /// The best [`SimpleModel::test_eval`](crate::SimpleModel::test_eval) metric, such as a
+    /// validation accuracy or loss
+    TestMetric {
+        /// Whether a higher metric is better, as for an accuracy
+        higher_is_better: bool,
+    },
+}
+
+impl BestBy {
+    /// The score of a step, where lower is better, or `None` if it has none
+    #[must_use]
+    pub fn score(self, hop: &HopRecord) -> Option<f64> {
+        let score = match self {
+            Self::Loss => Some(hop.loss),
+            Self::TestMetric { higher_is_better } => {
+                hop.test_metric
+                    .map(|m| if higher_is_better { -m } else { m })
+            }
+        };
+        score.filter(|s| !s.is_nan())
+    }
+}
+
+/// Record of a single basin hopping step
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HopRecord {
+    /// The index of the step
+    pub step: usize,
+    /// The name of the checkpoint file the minimised weights were saved to
+    pub name: String,
+    /// Whether the checkpoint file is still on disk, see [`crate::checkpoint::Retention`]
+    pub retained: bool,
+    /// The loss at the minimum, including the L2 term
+    #[serde(deserialize_with = "nan_if_null")]
+    pub loss: f64,
+    /// The L2 regularisation term
+    #[serde(deserialize_with = "nan_if_null")]
+    pub l2: f64,
+    /// The number of function evaluations used by the local minimiser
+    pub fn_evals: usize,
+    /// Whether the local minimiser converged
+    pub converged: bool,
+    /// The step size in effect for this step
+    pub step_size: f64,
+    /// The temperature used by the Metropolis criterion for this step
+    pub temperature: f64,
+    /// The outcome of the Metropolis criterion
+    pub outcome: HopOutcome,
+    /// The test metric of the minimum, or `None` if the minimum was not finite
+    #[serde(default)]
+    pub test_metric: Option<f64>,
+    /// The canonical ID of the minimum, if detecting duplicates and the minimum was finite,
+    /// see [`crate::duplicates`]
+    #[serde(default)]
+    pub minimum: Option<usize>,
+    /// When the step finished, in seconds since the unix epoch
+    pub timestamp: u64,
+}
+
+/// The result of a basin hopping run
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BhopResult {
+    /// The lowest loss found, including the L2 term
+    pub min_loss: f64,
+    /// The name of the checkpoint holding the lowest loss
+    pub min_name: String,
+    /// Record of every step taken
+    pub history: Vec<HopRecord>,
+    /// The number of accepted steps
+    pub n_accepted: usize,
+    /// The number of relaxations that ended with a non-finite loss or weights,
+    /// including those retried
+    #[serde(default)]
+    pub n_non_finite: usize,
+    /// The step size at the end of the run
+    pub step_size: f64,
+    /// Why the run stopped, or `None` while it is in progress
+    #[serde(default)]
+    pub stop_reason: Option<StopReason>,
+    /// How the step reported by [`best`](Self::best) is chosen
+    #[serde(default)]
+    pub best_by: BestBy,
+}
+
+impl BhopResult {
+    pub(crate) fn new(step_size: f64, best_by: BestBy) -> Self {
+        Self {
+            min_loss: f64::INFINITY,
+            min_name: String::new(),
+            history: Vec::new(),
+            n_accepted: 0,
+            n_non_finite: 0,
+            step_size,
+            stop_reason: None,
+            best_by,
+        }
+    }
+
+    /// The number of steps taken
+    #[must_use]
+    pub fn n_hops(&self) -> usize {
+        self.history.len()
+    }
+
+    /// The number of rejected steps
+    #[must_use]
+    pub fn n_rejected(&self) -> usize {
+        self.n_hops() - self.n_accepted
+    }
+
+    /// The fraction of steps accepted
+    #[must_use]
+    pub fn acceptance_rate(&self) -> f64 {
+        #[allow(clippy::cast_precision_loss)]
+        if self.history.is_empty() {
+            0.
+        } else {
+            self.n_accepted as f64 / self.n_hops() as f64
+        }
+    }
+
+    /// The record of the best step, by [`best_by`](Self::best_by): the one holding the lowest
+    /// loss by default
+    #[must_use]
+    pub fn best(&self) -> Option<&HopRecord> {
+        match self.best_by {
+            BestBy::Loss => self.history.iter().find(|h| h.name == self.min_name),
+            BestBy::TestMetric { .. } => self
+                .history
+                .iter()
+                .filter_map(|h| Some((h, self.best_by.score(h)?)))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(h, _)| h),
+        }
+    }
+
+    /// The score of the best step, where lower is better, used to compare runs
+    pub(crate) fn best_score(&self) -> f64 {
+        self.best()
+            .and_then(|h| self.best_by.score(h))
+            .unwrap_or(f64::INFINITY)
+    }
+
+    /// The number of unique minima found, if detecting duplicates
+    #[must_use]
+    pub fn n_unique_minima(&self) -> usize {
+        self.history
+            .iter()
+            .filter_map(|h| h.minimum)
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    /// The names of all the checkpoint files written
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(|h| h.name.as_str())
+    }
+
+    /// The names of the checkpoint files still on disk
+    pub fn retained_names(&self) -> impl Iterator<Item = &str> {
+        self.history
+            .iter()
+            .filter(|h| h.retained)
+            .map(|h| h.name.as_str())
+    }
+}
+
+/// serde_json writes non-finite floats as null, so read null back as NaN
+fn nan_if_null<'de, D: Deserializer<'de>>(d: D) -> Result<f64, D::Error> {
+    Ok(Option::<f64>::deserialize(d)?.unwrap_or(f64::NAN))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::hop_record;
+
+    fn with_metric(step: usize, loss: f64, test_metric: Option<f64>) -> HopRecord {
+        HopRecord {
+            test_metric,
+            ..hop_record(step, loss, HopOutcome::Decrease)
+        }
+    }
+
+    #[test]
+    fn best_by_scores_lower_as_better() {
+        let hop = with_metric(0, 2., Some(0.75));
+        let higher = BestBy::TestMetric {
+            higher_is_better: true,
+        };
+        let lower = BestBy::TestMetric {
+            higher_is_better: false,
+        };
+        assert_eq!(BestBy::Loss.score(&hop), Some(2.));
+        assert_eq!(higher.score(&hop), Some(-0.75));
+        assert_eq!(lower.score(&hop), Some(0.75));
+        assert_eq!(higher.score(&with_metric(0, 2., None)), None);
+        assert_eq!(higher.score(&with_metric(0, 2., Some(f64::NAN))), None);
+        assert_eq!(BestBy::Loss.score(&with_metric(0, f64::NAN, None)), None);
+    }
+
+    #[test]
+    fn best_step_follows_best_by() {
+        let mut result = BhopResult::new(1., BestBy::Loss);
+        result.history = vec![
+            with_metric(0, 3., Some(0.9)),
+            with_metric(1, 1., Some(0.5)),
+            with_metric(2, 2., None),
+            with_metric(3, f64::NAN, Some(f64::NAN)),
+        ];
+        result.min_name = result.history[1].name.clone();
+        assert_eq!(result.best().map(|h| h.step), Some(1));
+        assert_eq!(result.best_score(), 1.);
+
+        result.best_by = BestBy::TestMetric {
+            higher_is_better: true,
+        };
+        assert_eq!(result.best().map(|h| h.step), Some(0));
+        assert_eq!(result.best_score(), -0.9);
+        result.best_by = BestBy::TestMetric {
+            higher_is_better: false,
+        };
+        assert_eq!(result.best().map(|h| h.step), Some(1));
+
+        let empty = BhopResult::new(1., BestBy::Loss);
+        assert_eq!(empty.best(), None);
+        assert_eq!(empty.best_score(), f64::INFINITY);
+    }
+}
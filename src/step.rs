@@ -0,0 +1,286 @@
+/*!
+Step taking strategies used to propose a new starting point for each basin hop
+*/
+
+use candle_core::{DType, Device, Tensor, Var};
+use rand::{distributions::Uniform, prelude::Distribution, RngCore};
+use rand_distr::StandardNormal;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// Trait for the strategy used to perturb the variables between basin hopping steps
+///
+/// Implemented for the built in [`UniformStep`] and [`GaussianStep`], as well as any closure
+/// with the signature `FnMut(&[Var], f64, &mut dyn RngCore) -> candle_core::Result<()>`
+///
+/// All randomness should be drawn from `rng`, which is seeded from [`crate::BhopConfig::seed`],
+/// so that runs can be reproduced exactly.
+///
+/// `take_step` is called once per hop with every variable being perturbed, unless a
+/// [`StepScale`] is set: then it is called once for each variable, in name order, with a
+/// one-element slice and the step size scaled for that variable. A step taker which couples
+/// the variables, for example by normalising the whole step, only sees one at a time then.
+pub trait StepTaker {
+    /// Perturb the variables in place, with the magnitude of the step set by `step_size`
+    fn take_step(
+        &mut self,
+        vars: &[Var],
+        step_size: f64,
+        rng: &mut dyn RngCore,
+    ) -> candle_core::Result<()>;
+}
+
+/// Sample a tensor with the shape, dtype and device of `t` from `dist`
+///
+/// The values are drawn on the CPU from `rng` and then moved to the device,
+/// so they do not depend on the device's own random number generator
+pub fn sample_like<D: Distribution<f64>>(
+    t: &Tensor,
+    dist: D,
+    rng: &mut dyn RngCore,
+) -> candle_core::Result<Tensor> {
+    let data: Vec<f64> = dist.sample_iter(rng).take(t.elem_count()).collect();
+    Tensor::from_vec(data, t.shape(), &Device::Cpu)?
+        .to_dtype(t.dtype())?
+        .to_device(t.device())
+}
+
+/// Add noise drawn uniformly from `[-step_size, step_size]` to every variable
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UniformStep;
+
+impl StepTaker for UniformStep {
+    fn take_step(
+        &mut self,
+        vars: &[Var],
+        step_size: f64,
+        rng: &mut dyn RngCore,
+    ) -> candle_core::Result<()> {
+        let dist = Uniform::new_inclusive(-step_size, step_size);
+        for v in vars {
+            let pert = sample_like(v.as_tensor(), dist, rng)?;
+            v.set(&v.add(&pert)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Add gaussian noise with mean 0 and standard deviation `step_size` to every variable
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GaussianStep;
+
+impl StepTaker for GaussianStep {
+    fn take_step(
+        &mut self,
+        vars: &[Var],
+        step_size: f64,
+        rng: &mut dyn RngCore,
+    ) -> candle_core::Result<()> {
+        for v in vars {
+            let pert = (sample_like(v.as_tensor(), StandardNormal, rng)? * step_size)?;
+            v.set(&v.add(&pert)?)?;
+        }
+        Ok(())
+    }
+}
+
+impl<F> StepTaker for F
+where
+    F: FnMut(&[Var], f64, &mut dyn RngCore) -> candle_core::Result<()>,
+{
+    fn take_step(
+        &mut self,
+        vars: &[Var],
+        step_size: f64,
+        rng: &mut dyn RngCore,
+    ) -> candle_core::Result<()> {
+        self(vars, step_size, rng)
+    }
+}
+
+/// Scaling of the step size for each variable, so a hop moves every layer by a comparable amount
+///
+/// When set, the step taker is called once per variable, in name order, with the step size
+/// multiplied by the factor for that variable.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StepScale {
+    /// Relative to the root mean square of each variable, at least `min` so that variables
+    /// initialised to zero still move
+    Rms {
+        /// The smallest factor
+        min: f64,
+    },
+    /// A factor for the variables whose names start with each prefix, using the longest
+    /// matching prefix, such as `("conv1", 0.1)`
+    Prefix {
+        /// Name prefixes and their factors
+        scales: Vec<(String, f64)>,
+        /// The factor for variables matching no prefix
+        default: f64,
+    },
+    /// `1 / sqrt(fan_in)`, where the fan-in is the product of every dimension but the first,
+    /// as for the `(out, in, ..)` weights of linear and convolutional layers. Vectors such as
+    /// biases have a fan-in of 1
+    FanIn,
+}
+
+impl StepScale {
+    /// The factor for the variable `name`
+    pub fn factor(&self, name: &str, var: &Var) -> Result<f64> {
+        Ok(match self {
+            Self::Rms { min } => {
+                let rms = var
+                    .as_tensor()
+                    .to_dtype(DType::F64)?
+                    .sqr()?
+                    .mean_all()?
+                    .sqrt()?
+                    .to_scalar::<f64>()?;
+                rms.max(*min)
+            }
+            Self::Prefix { scales, default } => scales
+                .iter()
+                .filter(|(prefix, _)| name.starts_with(prefix.as_str()))
+                .max_by_key(|(prefix, _)| prefix.len())
+                .map_or(*default, |(_, scale)| *scale),
+            Self::FanIn => {
+                let fan_in: usize = var.dims().iter().skip(1).product();
+                #[allow(clippy::cast_precision_loss)]
+                let fan_in = fan_in.max(1) as f64;
+                fan_in.sqrt().recip()
+            }
+        })
+    }
+}
+
+/// Adaptive control of the step size, in the style of scipy's `AdaptiveStepsize`
+///
+/// Every `interval` hops the acceptance rate of those last `interval` hops is compared to
+/// `accept_rate`: if more hops were accepted than targeted the step size is divided by
+/// `factor`, otherwise it is multiplied by it, within the optional bounds. Only the latest
+/// interval counts, so the always accepted first step does not hold back later adjustments.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveStepSize {
+    /// The number of hops between updates of the step size
+    pub interval: usize,
+    /// The target acceptance rate
+    pub accept_rate: f64,
+    /// The factor by which the step size is changed, in (0, 1)
+    pub factor: f64,
+    /// The smallest step size, if bounded
+    #[serde(default)]
+    pub min_step_size: Option<f64>,
+    /// The largest step size, if bounded
+    #[serde(default)]
+    pub max_step_size: Option<f64>,
+}
+
+impl Default for AdaptiveStepSize {
+    fn default() -> Self {
+        Self {
+            interval: 50,
+            accept_rate: 0.5,
+            factor: 0.9,
+            min_step_size: None,
+            max_step_size: None,
+        }
+    }
+}
+
+impl AdaptiveStepSize {
+    /// The new step size given the number of hops accepted in the last `interval`
+    #[must_use]
+    pub fn adjust(&self, step_size: f64, accepted: usize) -> f64 {
+        #[allow(clippy::cast_precision_loss)]
+        let rate = accepted as f64 / self.interval as f64;
+        let step_size = if rate > self.accept_rate {
+            // accepting too many steps: take larger ones
+            step_size / self.factor
+        } else {
+            step_size * self.factor
+        };
+        let step_size = self
+            .min_step_size
+            .map_or(step_size, |min| step_size.max(min));
+        self.max_step_size
+            .map_or(step_size, |max| step_size.min(max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_xoshiro::Xoshiro256StarStar;
+
+    /// take one step from zero and return the perturbation
+    fn draw(step: &mut impl StepTaker, step_size: f64, seed: u64) -> candle_core::Result<Vec<f64>> {
+        let var = Var::zeros(10_000, DType::F64, &Device::Cpu)?;
+        let mut rng = Xoshiro256StarStar::seed_from_u64(seed);
+        step.take_step(std::slice::from_ref(&var), step_size, &mut rng)?;
+        var.as_tensor().to_vec1::<f64>()
+    }
+
+    #[test]
+    fn step_scale_factors() -> Result<()> {
+        let w = Var::new(&[[3_f64, -3.], [3., 3.]], &Device::Cpu)?;
+        let prefix = StepScale::Prefix {
+            scales: vec![("dense".into(), 0.5), ("dense1.w".into(), 0.25)],
+            default: 2.,
+        };
+        assert_eq!(StepScale::Rms { min: 0.1 }.factor("w", &w)?, 3.);
+        assert_eq!(StepScale::Rms { min: 4. }.factor("w", &w)?, 4.);
+        assert_eq!(prefix.factor("dense1.weight", &w)?, 0.25);
+        assert_eq!(prefix.factor("dense2.weight", &w)?, 0.5);
+        assert_eq!(prefix.factor("conv1.weight", &w)?, 2.);
+        assert_eq!(StepScale::FanIn.factor("w", &w)?, 2_f64.sqrt().recip());
+        let bias = Var::new(&[1_f64, 2.], &Device::Cpu)?;
+        assert_eq!(StepScale::FanIn.factor("b", &bias)?, 1.);
+        Ok(())
+    }
+
+    #[test]
+    fn adaptive_step_grows_shrinks_and_clamps() {
+        let adaptive = AdaptiveStepSize {
+            interval: 10,
+            accept_rate: 0.5,
+            factor: 0.5,
+            min_step_size: None,
+            max_step_size: None,
+        };
+        assert_eq!(adaptive.adjust(1., 8), 2.);
+        assert_eq!(adaptive.adjust(1., 2), 0.5);
+        // exactly on target counts as too few
+        assert_eq!(adaptive.adjust(1., 5), 0.5);
+        let bounded = AdaptiveStepSize {
+            min_step_size: Some(0.8),
+            max_step_size: Some(1.5),
+            ..adaptive
+        };
+        assert_eq!(bounded.adjust(1., 8), 1.5);
+        assert_eq!(bounded.adjust(1., 2), 0.8);
+    }
+
+    #[test]
+    fn uniform_step_stays_in_range() -> candle_core::Result<()> {
+        let pert = draw(&mut UniformStep, 0.5, 1)?;
+        assert!(pert.iter().all(|p| p.abs() <= 0.5));
+        assert_eq!(pert, draw(&mut UniformStep, 0.5, 1)?);
+        Ok(())
+    }
+
+    #[test]
+    fn gaussian_step_has_step_size_deviation() -> candle_core::Result<()> {
+        let pert = draw(&mut GaussianStep, 0.5, 2)?;
+        #[allow(clippy::cast_precision_loss)]
+        let n = pert.len() as f64;
+        let mean = pert.iter().sum::<f64>() / n;
+        let std = (pert.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / n).sqrt();
+        assert!(mean.abs() < 0.02);
+        assert!((std - 0.5).abs() < 0.02);
+        assert_eq!(pert, draw(&mut GaussianStep, 0.5, 2)?);
+        assert_ne!(pert, draw(&mut GaussianStep, 0.5, 3)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+/*!
+Criteria for stopping a basin hopping run before all its steps are taken
+*/
+
+use std::fmt::Debug;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{BhopResult, HopOutcome, StopReason};
+
+/// A user supplied stop condition on the results so far
+pub type StopPredicate = Box<dyn Fn(&BhopResult) -> bool>;
+
+/// Conditions checked after every step, stopping the run as soon as one is met
+///
+/// By default none are set, and the run takes every step
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct StopCriteria {
+    /// Stop after this many consecutive steps without a new global minimum
+    /// (`niter_success` in scipy)
+    pub no_improvement: Option<usize>,
+    /// Stop once the lowest loss is below this target
+    pub target_loss: Option<f64>,
+    /// Stop once this much time has passed since the run (or the resumed session) started
+    pub time_limit: Option<Duration>,
+    /// Stop when this returns true for the results so far
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub predicate: Option<StopPredicate>,
+}
+
+impl StopCriteria {
+    /// The first criterion met by `result`, `elapsed` after the start of the session
+    #[must_use]
+    pub fn check(&self, result: &BhopResult, elapsed: Duration) -> Option<StopReason> {
+        if let Some(k) = self.no_improvement {
+            let since_min = result
+                .history
+                .iter()
+                .rev()
+                .take_while(|h| h.outcome != HopOutcome::NewGlobalMin)
+                .count();
+            if since_min >= k {
+                return Some(StopReason::NoImprovement);
+            }
+        }
+        if self.target_loss.is_some_and(|t| result.min_loss < t) {
+            return Some(StopReason::TargetLoss);
+        }
+        if self.time_limit.is_some_and(|t| elapsed >= t) {
+            return Some(StopReason::TimeLimit);
+        }
+        if self.predicate.as_ref().is_some_and(|p| p(result)) {
+            return Some(StopReason::Predicate);
+        }
+        None
+    }
+}
+
+impl Debug for StopCriteria {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StopCriteria")
+            .field("no_improvement", &self.no_improvement)
+            .field("target_loss", &self.target_loss)
+            .field("time_limit", &self.time_limit)
+            .field("predicate", &self.predicate.as_ref().map(|_| "Custom"))
+            .finish()
+    }
+}
@@ -0,0 +1,143 @@
+/*!
+Temperature schedules for the Metropolis acceptance criterion
+*/
+
+use std::fmt::Debug;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The temperature used by the Metropolis criterion at each basin hopping step
+///
+/// Decreasing schedules give simulated annealing: the walk explores widely at first,
+/// then settles into the best funnel found.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TemperatureSchedule {
+    /// The same temperature for every step
+    Constant(f64),
+    /// Interpolate linearly from `start` at the first step to `end` at the last
+    Linear {
+        /// The temperature at the first step
+        start: f64,
+        /// The temperature at the last step
+        end: f64,
+    },
+    /// Multiply the temperature by `decay` after every step
+    Exponential {
+        /// The temperature at the first step
+        start: f64,
+        /// The factor applied each step, in (0, 1) for cooling
+        decay: f64,
+    },
+    /// Logarithmic cooling, with temperature `start * ln(2) / ln(step + 2)`
+    Logarithmic {
+        /// The temperature at the first step
+        start: f64,
+    },
+    /// A user supplied function of the step index
+    ///
+    /// The run stops with [`ConfigError::Temperature`](crate::builder::ConfigError::Temperature)
+    /// at the first step whose temperature is not finite and positive
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(Box<dyn Fn(usize) -> f64>),
+}
+
+impl TemperatureSchedule {
+    /// The temperature at `step` of a run of `steps` steps
+    #[must_use]
+    pub fn temperature(&self, step: usize, steps: usize) -> f64 {
+        #[allow(clippy::cast_precision_loss)]
+        match self {
+            Self::Constant(t) => *t,
+            Self::Linear { start, end } => {
+                if steps > 1 {
+                    start + (end - start) * step as f64 / (steps - 1) as f64
+                } else {
+                    *start
+                }
+            }
+            Self::Exponential { start, decay } => {
+                start * decay.powi(i32::try_from(step).unwrap_or(i32::MAX))
+            }
+            Self::Logarithmic { start } => start * 2_f64.ln() / (step as f64 + 2.).ln(),
+            Self::Custom(f) => f(step),
+        }
+    }
+}
+
+impl From<f64> for TemperatureSchedule {
+    fn from(temperature: f64) -> Self {
+        Self::Constant(temperature)
+    }
+}
+
+impl Debug for TemperatureSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Constant(t) => f.debug_tuple("Constant").field(t).finish(),
+            Self::Linear { start, end } => f
+                .debug_struct("Linear")
+                .field("start", start)
+                .field("end", end)
+                .finish(),
+            Self::Exponential { start, decay } => f
+                .debug_struct("Exponential")
+                .field("start", start)
+                .field("decay", decay)
+                .finish(),
+            Self::Logarithmic { start } => {
+                f.debug_struct("Logarithmic").field("start", start).finish()
+            }
+            Self::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn linear_hits_both_endpoints() {
+        let linear = TemperatureSchedule::Linear {
+            start: 2.,
+            end: 0.5,
+        };
+        assert!(close(linear.temperature(0, 4), 2.));
+        assert!(close(linear.temperature(3, 4), 0.5));
+        assert!(close(linear.temperature(0, 1), 2.));
+    }
+
+    #[test]
+    fn exponential_decays_by_a_constant_ratio() {
+        let exponential = TemperatureSchedule::Exponential {
+            start: 3.,
+            decay: 0.8,
+        };
+        assert!(close(exponential.temperature(0, 10), 3.));
+        for step in 0..9 {
+            let ratio = exponential.temperature(step + 1, 10) / exponential.temperature(step, 10);
+            assert!(close(ratio, 0.8));
+        }
+    }
+
+    #[test]
+    fn logarithmic_starts_at_start() {
+        let logarithmic = TemperatureSchedule::Logarithmic { start: 1.5 };
+        assert!(close(logarithmic.temperature(0, 10), 1.5));
+        assert!(logarithmic.temperature(1, 10) < 1.5);
+        assert!(logarithmic.temperature(1000, 10).is_finite());
+    }
+
+    #[test]
+    fn custom_is_passed_the_step() {
+        #[allow(clippy::cast_precision_loss)]
+        let custom = TemperatureSchedule::Custom(Box::new(|step| step as f64 * 0.25));
+        assert!(close(custom.temperature(0, 10), 0.));
+        assert!(close(custom.temperature(7, 10), 1.75));
+    }
+}
@@ -0,0 +1,283 @@
+/*!
+Parallel tempering (replica exchange) basin hopping
+
+Several walkers, each with its own copy of the model, hop at different temperatures.
+Periodically neighbouring replicas attempt to swap their current minima, so configurations
+found by the hot walkers can move down to the cold ones, helping the cold walkers escape
+the funnel they are stuck in.
+*/
+
+use std::path::Path;
+use std::time::Instant;
+
+use candle_nn::{VarBuilder, VarMap};
+use log::info;
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::Xoshiro256StarStar;
+
+use crate::builder::{check_temperature, ConfigError};
+use crate::checkpoint::{Restore, Snapshot};
+use crate::mask::check_mask;
+use crate::minimiser::LbfgsMinimiser;
+use crate::temperature::TemperatureSchedule;
+use crate::walker::Walker;
+use crate::{
+    create_output_dir, BhopConfig, BhopResult, Error, Manifest, Result, SimpleModel, StopReason,
+};
+
+/// Settings for parallel tempering
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemperingConfig {
+    /// The temperature of each replica, usually in increasing order, at least two
+    pub temperatures: Vec<f64>,
+    /// The number of basin hopping steps between attempts to swap replicas, positive
+    pub swap_interval: usize,
+}
+
+impl TemperingConfig {
+    /// Check the settings are valid, as done before every run
+    pub fn validate(&self) -> std::result::Result<(), ConfigError> {
+        if self.temperatures.len() < 2 {
+            return Err(ConfigError::TooFewReplicas(self.temperatures.len()));
+        }
+        if self.swap_interval == 0 {
+            return Err(ConfigError::NoSwapInterval);
+        }
+        self.temperatures
+            .iter()
+            .try_for_each(|&t| check_temperature(t))
+    }
+}
+
+/// The result of a parallel tempering run
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemperingResult {
+    /// The result of each replica, in the order of the temperatures
+    pub replicas: Vec<BhopResult>,
+    /// The temperature of each replica
+    pub temperatures: Vec<f64>,
+    /// The number of swaps attempted between replica `k` and `k + 1`
+    pub swap_attempts: Vec<usize>,
+    /// The number of swaps accepted between replica `k` and `k + 1`
+    pub swap_accepts: Vec<usize>,
+}
+
+impl TemperingResult {
+    /// The fraction of attempted swaps accepted between replica `k` and `k + 1`
+    #[must_use]
+    pub fn swap_rate(&self, k: usize) -> f64 {
+        #[allow(clippy::cast_precision_loss)]
+        if self.swap_attempts[k] == 0 {
+            0.
+        } else {
+            self.swap_accepts[k] as f64 / self.swap_attempts[k] as f64
+        }
+    }
+
+    /// The index and result of the replica with the best step, by
+    /// [`BhopResult::best_by`]: the lowest loss by default
+    #[must_use]
+    pub fn best(&self) -> Option<(usize, &BhopResult)> {
+        self.replicas
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.best_score().total_cmp(&b.best_score()))
+    }
+}
+
+/// Run parallel tempering basin hopping
+///
+/// `model` and `varmap` are used as the first replica, and the others are created with
+/// [`SimpleModel::new`] from `setup_vars` and start from a copy of the weights in `varmap`.
+/// Replica `k` writes its checkpoints and manifest to `path/replica_k`, and uses the seed
+/// `config.seed + k`.
+///
+/// The temperature schedule of `config` is replaced by the fixed temperature of each replica,
+/// and the current minima are always kept in memory ([`Restore::Memory`]) so they can be swapped.
+/// The observer of `config` is shared by the replicas, and stops the run at the end of the
+/// current round of steps. The stop criteria are checked against every replica after each
+/// round, and stop them all once any replica meets one. Parallel tempering runs cannot be resumed.
+pub fn parallel_tempering<M: SimpleModel, P: AsRef<Path>>(
+    model: &M,
+    varmap: VarMap,
+    setup_vars: &M::SetupVars,
+    path: P,
+    mut config: BhopConfig,
+    tempering: TemperingConfig,
+) -> Result<TemperingResult>
+where
+    M::SetupVars: Clone,
+{
+    let path: &Path = path.as_ref();
+    let n_replicas = tempering.temperatures.len();
+    tempering.validate()?;
+    config.restore = Restore::Memory;
+    config.validate()?;
+    check_mask(&varmap, config.mask.as_ref())?;
+    create_output_dir(path)?;
+
+    // build the other replicas, starting from the same weights
+    let (dtype, device) = {
+        let vars = varmap.all_vars();
+        let var = vars.first().ok_or(Error::NoVariables)?;
+        (var.dtype(), var.device().clone())
+    };
+    let initial = Snapshot::take(&varmap)?;
+    let mut replicas = Vec::with_capacity(n_replicas - 1);
+    for _ in 1..n_replicas {
+        let replica_varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&replica_varmap, dtype, &device);
+        let replica = M::new(vs, setup_vars.clone())?;
+        initial.restore(&replica_varmap)?;
+        replicas.push((replica, replica_varmap));
+    }
+
+    let models =
+        std::iter::once((model, varmap)).chain(replicas.iter().map(|(m, v)| (m, v.clone())));
+    let mut walkers = Vec::with_capacity(n_replicas);
+    for (k, ((model, varmap), &temperature)) in models.zip(&tempering.temperatures).enumerate() {
+        let replica_path = path.join(format!("replica_{k}"));
+        create_output_dir(&replica_path)?;
+        let seed = config.seed.wrapping_add(k as u64);
+        let mut state = Manifest::new(&config);
+        state.rng = Xoshiro256StarStar::seed_from_u64(seed);
+        state.config.seed = seed;
+        state.config.temperature = format!("{:?}", TemperatureSchedule::Constant(temperature));
+        walkers.push(Walker::new(model, varmap, &replica_path, state, &config)?);
+    }
+
+    // separate stream for the swaps, so they do not disturb the replicas
+    let mut rng = Xoshiro256StarStar::seed_from_u64(config.seed);
+    rng.long_jump();
+    let mut swap_attempts = vec![0; n_replicas - 1];
+    let mut swap_accepts = vec![0; n_replicas - 1];
+    let mut swap_round = 0;
+    let mut minimiser = LbfgsMinimiser::from(&config);
+
+    let started = Instant::now();
+    let mut stop_reason = StopReason::Completed;
+    for i in 0..config.steps {
+        let mut stop = false;
+        for (k, walker) in walkers.iter_mut().enumerate() {
+            info!("replica {} at temperature {}", k, tempering.temperatures[k]);
+            stop |= walker
+                .hop(&mut config, &mut minimiser, i, tempering.temperatures[k])?
+                .is_break();
+        }
+        if stop {
+            stop_reason = StopReason::Observer;
+            break;
+        }
+        if (i + 1).is_multiple_of(tempering.swap_interval) {
+            // alternate between swapping the even and the odd pairs
+            for k in (swap_round % 2..n_replicas - 1).step_by(2) {
+                let (cold, hot) = walkers.split_at_mut(k + 1);
+                let (a, b) = (&mut cold[k], &mut hot[0]);
+                let beta_a = 1. / tempering.temperatures[k];
+                let beta_b = 1. / tempering.temperatures[k + 1];
+                let p = ((beta_a - beta_b) * (a.state.current_loss - b.state.current_loss)).exp();
+                swap_attempts[k] += 1;
+                if rng.gen_range(0_f64..1.) < p {
+                    info!(
+                        "SWAP: replicas {} and {}, losses {} and {}",
+                        k,
+                        k + 1,
+                        a.state.current_loss,
+                        b.state.current_loss
+                    );
+                    swap_accepts[k] += 1;
+                    swap_minima(a, b)?;
+                }
+            }
+            swap_round += 1;
+        }
+        let elapsed = started.elapsed();
+        if let Some(reason) = walkers
+            .iter()
+            .find_map(|w| config.stop.check(&w.state.result, elapsed))
+        {
+            info!("stopping early after step {}: {:?}", i, reason);
+            stop_reason = reason;
+            break;
+        }
+    }
+    for walker in &mut walkers {
+        walker.state.result.stop_reason = Some(stop_reason);
+        if !walker.state.result.history.is_empty() {
+            walker.state.write(&walker.path)?;
+        }
+    }
+
+    for (k, (attempts, accepts)) in swap_attempts.iter().zip(&swap_accepts).enumerate() {
+        info!(
+            "swaps between replicas {} and {}: {}/{}",
+            k,
+            k + 1,
+            accepts,
+            attempts
+        );
+    }
+    Ok(TemperingResult {
+        replicas: walkers.into_iter().map(|w| w.state.result).collect(),
+        temperatures: tempering.temperatures,
+        swap_attempts,
+        swap_accepts,
+    })
+}
+
+/// Exchange the current minima of two walkers
+fn swap_minima<M: SimpleModel>(a: &mut Walker<M>, b: &mut Walker<M>) -> Result<()> {
+    // checkpoint names are relative to the replica's own directory, which are siblings
+    let sibling = |walker: &Walker<M>| {
+        let name = &walker.state.current_name;
+        if name.starts_with("../") {
+            name.clone()
+        } else {
+            let dir = walker.path.file_name().unwrap_or_default();
+            format!("../{}/{}", dir.to_string_lossy(), name)
+        }
+    };
+    let (name_a, name_b) = (sibling(a), sibling(b));
+    a.state.current_name = name_b;
+    b.state.current_name = name_a;
+    std::mem::swap(&mut a.state.current_loss, &mut b.state.current_loss);
+    std::mem::swap(&mut a.snapshot, &mut b.snapshot);
+    a.restore_current()?;
+    b.restore_current()?;
+    a.state.write(&a.path)?;
+    b.state.write(&b.path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tempering(temperatures: Vec<f64>, swap_interval: usize) -> TemperingConfig {
+        TemperingConfig {
+            temperatures,
+            swap_interval,
+        }
+    }
+
+    #[test]
+    fn validate_rejects_configs_that_cannot_swap() {
+        assert_eq!(tempering(vec![0.1, 1.], 1).validate(), Ok(()));
+        assert_eq!(
+            tempering(vec![0.1, 1.], 0).validate(),
+            Err(ConfigError::NoSwapInterval)
+        );
+        assert_eq!(
+            tempering(vec![1.], 1).validate(),
+            Err(ConfigError::TooFewReplicas(1))
+        );
+        assert_eq!(
+            tempering(Vec::new(), 1).validate(),
+            Err(ConfigError::TooFewReplicas(0))
+        );
+        assert_eq!(
+            tempering(vec![1., -1.], 1).validate(),
+            Err(ConfigError::Temperature(-1.))
+        );
+    }
+}
@@ -1,17 +1,32 @@
-use candle_core::Var;
-use candle_nn::VarMap;
+use candle_core::{backprop::GradStore, Tensor, Var};
+use candle_nn::{Optimizer, VarMap};
 use log::{debug, info, warn};
 use optimisers::lbfgs::{Lbfgs, ParamsLBFGS};
 use optimisers::LossOptimizer;
 
-use crate::SimpleModel;
+use crate::observer::BhopObserver;
+use crate::{BatchedModel, Error, Result, SimpleModel};
+
+/// The outcome of a local minimisation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Relaxation {
+    /// The final loss, excluding any L2 term
+    pub loss: f64,
+    /// The number of function evaluations used
+    pub fn_evals: usize,
+    /// Whether the minimisation converged
+    pub converged: bool,
+    /// Whether an observer stopped the minimisation
+    pub stopped: bool,
+}
 
 pub(super) fn run_lbfgs_training<M: SimpleModel>(
     model: &M,
-    varmap: &VarMap,
+    vars: Vec<Var>,
     params: ParamsLBFGS,
     lbfgs_steps: usize,
-) -> anyhow::Result<f64> {
+    observer: &mut dyn BhopObserver,
+) -> Result<Relaxation> {
     let mut loss = model.loss()?;
     info!(
         "initial loss: {}",
@@ -19,9 +34,10 @@ pub(super) fn run_lbfgs_training<M: SimpleModel>(
     );
 
     // create an optimiser
-    let mut optimiser = Lbfgs::new(varmap.all_vars(), params, model.clone())?;
+    let mut optimiser = Lbfgs::new(vars, params, model.clone())?;
     let mut fn_evals = 1;
     let mut converged = false;
+    let mut stopped = false;
 
     for step in 0..lbfgs_steps {
         // step the tensors by backpropagating the loss
@@ -35,11 +51,11 @@ pub(super) fn run_lbfgs_training<M: SimpleModel>(
                         .to_dtype(candle_core::DType::F64)?
                         .to_scalar::<f64>()?
                 );
-                info!("test metric: {}", model.test_eval()?);
                 fn_evals += evals;
                 loss = new_loss;
                 converged = true;
                 info!("converged after {} fn evals", fn_evals);
+                stopped = observer.on_lbfgs_step(step, to_f64(&loss)?).is_break();
                 break;
             }
             optimisers::ModelOutcome::Stepped(new_loss, evals) => {
@@ -53,11 +69,15 @@ pub(super) fn run_lbfgs_training<M: SimpleModel>(
                 debug!("test acc: {:5.2}", model.test_eval()?);
                 fn_evals += evals;
                 loss = new_loss;
+                if observer.on_lbfgs_step(step, to_f64(&loss)?).is_break() {
+                    info!("stopped by observer after {} fn evals", fn_evals);
+                    stopped = true;
+                    break;
+                }
             }
         }
     }
-    if !converged {
-        info!("test acc: {:5.2}", model.test_eval()?);
+    if !converged && !stopped {
         warn!("did not converge after {} fn evals", fn_evals);
     }
     info!(
@@ -65,7 +85,107 @@ pub(super) fn run_lbfgs_training<M: SimpleModel>(
         loss.to_dtype(candle_core::DType::F64)?.to_scalar::<f64>()?
     );
     info!("{} fn evals", fn_evals);
-    Ok(loss.to_dtype(candle_core::DType::F64)?.to_scalar::<f64>()?)
+    Ok(Relaxation {
+        loss: loss.to_dtype(candle_core::DType::F64)?.to_scalar::<f64>()?,
+        fn_evals,
+        converged,
+        stopped,
+    })
+}
+
+/// Relax with a first order optimiser, until the change in loss over a step is below `loss_tol`
+///
+/// `evaluate` returns the loss and its gradients at the current variables
+pub(super) fn run_first_order_training<O: Optimizer>(
+    mut optimiser: O,
+    steps: usize,
+    loss_tol: f64,
+    observer: &mut dyn BhopObserver,
+    mut evaluate: impl FnMut() -> Result<(f64, GradStore)>,
+) -> Result<Relaxation> {
+    let (mut loss, mut grads) = evaluate()?;
+    info!("initial loss: {}", loss);
+    let mut fn_evals = 1;
+    let mut converged = false;
+    let mut stopped = false;
+
+    for step in 0..steps {
+        optimiser.step(&grads)?;
+        let prev = loss;
+        (loss, grads) = evaluate()?;
+        fn_evals += 1;
+        debug!("step: {}", step);
+        debug!("loss: {}", loss);
+        if (prev - loss).abs() < loss_tol {
+            converged = true;
+            info!("converged after {} fn evals", fn_evals);
+        }
+        if observer.on_lbfgs_step(step, loss).is_break() {
+            info!("stopped by observer after {} fn evals", fn_evals);
+            stopped = true;
+        }
+        if converged || stopped {
+            break;
+        }
+    }
+    if !converged && !stopped {
+        warn!("did not converge after {} fn evals", fn_evals);
+    }
+    info!("loss: {}", loss);
+    Ok(Relaxation {
+        loss,
+        fn_evals,
+        converged,
+        stopped,
+    })
+}
+
+/// The full batch loss of the model and its gradients
+pub(super) fn full_batch_gradients<M: SimpleModel>(model: &M) -> Result<(f64, GradStore)> {
+    let loss = model.loss()?;
+    let grads = loss.backward()?;
+    Ok((
+        loss.to_dtype(candle_core::DType::F64)?.to_scalar::<f64>()?,
+        grads,
+    ))
+}
+
+/// The loss of the model and its gradients with respect to `vars`, summed over the batches
+///
+/// Only one batch is held on the device at a time
+pub(super) fn batched_gradients<M: BatchedModel>(
+    model: &M,
+    vars: &[Var],
+) -> Result<(f64, GradStore)> {
+    let mut loss = 0.;
+    let mut total: Option<GradStore> = None;
+    for batch in 0..model.n_batches() {
+        let batch_loss = model.batch_loss(batch)?;
+        loss += batch_loss
+            .to_dtype(candle_core::DType::F64)?
+            .to_scalar::<f64>()?;
+        let grads = batch_loss.backward()?;
+        match &mut total {
+            None => total = Some(grads),
+            Some(total) => {
+                for var in vars {
+                    if let Some(grad) = grads.get(var) {
+                        let sum = match total.get(var) {
+                            Some(acc) => (acc + grad)?,
+                            None => grad.clone(),
+                        };
+                        total.insert(var, sum);
+                    }
+                }
+            }
+        }
+    }
+    let total = total.ok_or(Error::NoBatches)?;
+    Ok((loss, total))
+}
+
+fn to_f64(loss: &Tensor) -> candle_core::Result<f64> {
+    loss.to_dtype(candle_core::DType::F64)?.to_scalar::<f64>()
 }
 
 pub(super) fn l2_norm(vs: &[Var]) -> candle_core::Result<f64> {
@@ -80,3 +200,22 @@ pub(super) fn l2_norm(vs: &[Var]) -> candle_core::Result<f64> {
     }
     Ok(norm)
 }
+
+/// All the variables of the varmap, in a fixed order (sorted by name)
+///
+/// Iteration order over a [`VarMap`] varies between instances,
+/// so this is needed for runs to be reproducible
+pub(super) fn sorted_vars(varmap: &VarMap) -> Vec<Var> {
+    sorted_named_vars(varmap)
+        .into_iter()
+        .map(|(_, v)| v)
+        .collect()
+}
+
+/// The variables of `varmap` with their names, in name order
+pub(super) fn sorted_named_vars(varmap: &VarMap) -> Vec<(String, Var)> {
+    let data = varmap.data().lock().unwrap();
+    let mut vars: Vec<_> = data.iter().map(|(n, v)| (n.clone(), v.clone())).collect();
+    vars.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    vars
+}
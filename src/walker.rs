@@ -0,0 +1,308 @@
+/*!
+A single basin hopping walker, taking one step at a time
+*/
+
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+
+use candle_nn::VarMap;
+use log::{info, warn};
+use rand::Rng;
+
+use crate::builder::check_temperature;
+use crate::checkpoint::{remove_checkpoints, Restore, Retention, Snapshot};
+use crate::duplicates::KnownMinima;
+use crate::manifest::{timestamp, Manifest};
+use crate::mask::masked_vars;
+use crate::minimiser::LocalMinimiser;
+use crate::nonfinite::{all_finite, NonFinitePolicy};
+use crate::training::{l2_norm, sorted_vars, Relaxation};
+use crate::{BhopConfig, Error, HopOutcome, HopRecord, Result, SimpleModel};
+
+/// A model, its variables and the state of its basin hopping run
+pub(crate) struct Walker<'a, M: SimpleModel> {
+    pub model: &'a M,
+    pub varmap: VarMap,
+    /// The output directory for checkpoints and the manifest
+    pub path: PathBuf,
+    pub state: Manifest,
+    /// copy of the current minimum, if restoring from memory
+    pub snapshot: Option<Snapshot>,
+    /// the unique minima found, if detecting duplicates
+    pub known: Option<KnownMinima>,
+}
+
+impl<'a, M: SimpleModel> Walker<'a, M> {
+    /// Create a walker whose variables hold the current minimum of `state`
+    pub fn new(
+        model: &'a M,
+        varmap: VarMap,
+        path: &Path,
+        state: Manifest,
+        config: &BhopConfig,
+    ) -> Result<Self> {
+        let snapshot = if config.restore == Restore::Memory && state.next_step > 0 {
+            Some(Snapshot::take(&varmap)?)
+        } else {
+            None
+        };
+        let known = config
+            .duplicates
+            .map(|d| KnownMinima::from_history(d, &state.result.history, path))
+            .transpose()?;
+        Ok(Self {
+            model,
+            varmap,
+            path: path.to_path_buf(),
+            state,
+            snapshot,
+            known,
+        })
+    }
+
+    /// Take basin hopping step `i` at the given temperature, and write the manifest
+    ///
+    /// Breaks if the observer asks for the run to stop
+    pub fn hop(
+        &mut self,
+        config: &mut BhopConfig,
+        minimiser: &mut impl LocalMinimiser<M>,
+        i: usize,
+        temperature: f64,
+    ) -> Result<ControlFlow<()>> {
+        check_temperature(temperature)?;
+        if config.observer.on_hop_start(i, temperature).is_break() {
+            info!("stopped by observer before step {}", i);
+            return Ok(ControlFlow::Break(()));
+        }
+        info!("Epoch {}", i);
+        let name = format!("model_{:03}.st", i);
+        let mut step_size = self.state.result.step_size;
+        let mut retries = 0;
+        let mut stop = false;
+        let (relaxation, l2_fac, loss, finite) = loop {
+            let (relaxation, l2_fac, loss) =
+                self.perturb_and_relax(config, minimiser, i, step_size)?;
+            stop |= relaxation.stopped;
+            if loss.is_finite() && all_finite(&sorted_vars(&self.varmap))? {
+                break (relaxation, l2_fac, loss, true);
+            }
+            self.state.result.n_non_finite += 1;
+            warn!("non-finite loss or weights at step {}, loss {}", i, loss);
+            stop |= config
+                .observer
+                .on_non_finite(i, loss, &relaxation, retries)
+                .is_break();
+            // the first step has no minimum to fall back on
+            if i == 0 || config.non_finite == NonFinitePolicy::Abort {
+                return Err(Error::NonFiniteLoss { step: i, loss });
+            }
+            self.restore_current()?;
+            match config.non_finite {
+                NonFinitePolicy::Retry {
+                    max_retries,
+                    factor,
+                } if retries < max_retries && !stop => {
+                    retries += 1;
+                    step_size *= factor;
+                    info!("retrying step {} with step size {}", i, step_size);
+                }
+                _ => break (relaxation, l2_fac, loss, false),
+            }
+        };
+
+        let retained = finite && config.retention != Retention::None;
+        let mut minimum = None;
+        let mut test_metric = None;
+        let outcome = if finite {
+            info!("loss inc L2: {}", loss);
+            info!("L2 reg: {}", l2_fac);
+            let metric = f64::from(self.model.test_eval()?);
+            info!("test metric: {}", metric);
+            test_metric = Some(metric);
+            if let Some(known) = &mut self.known {
+                let id = known.classify(loss, &sorted_vars(&self.varmap))?;
+                info!("minimum {}", id);
+                minimum = Some(id);
+            }
+            stop |= config
+                .observer
+                .on_minimised(i, loss, &relaxation)
+                .is_break();
+            if retained {
+                self.varmap.save(self.path.join(&name))?;
+            }
+            self.metropolis(loss, &name, temperature)?
+        } else {
+            info!("NOSTEP: non-finite loss {}", loss);
+            HopOutcome::NonFinite
+        };
+        if outcome.accepted() {
+            self.state.result.n_accepted += 1;
+            if config.restore == Restore::Memory {
+                self.snapshot = Some(Snapshot::take(&self.varmap)?);
+            }
+        }
+        self.state.result.history.push(HopRecord {
+            step: i,
+            name,
+            retained,
+            loss,
+            l2: l2_fac,
+            fn_evals: relaxation.fn_evals,
+            converged: relaxation.converged,
+            step_size,
+            temperature,
+            outcome,
+            test_metric,
+            minimum,
+            timestamp: timestamp(),
+        });
+        // the lowest loss, and the best step if chosen by test metric
+        let mut best = vec![self.state.result.min_name.clone()];
+        best.extend(self.state.result.best().map(|h| h.name.clone()));
+        let removed = config.retention.apply(
+            &mut self.state.result.history,
+            &self.state.current_name,
+            &best,
+        );
+        if let Some(adaptive) = config.adaptive_step {
+            if (i + 1).is_multiple_of(adaptive.interval) {
+                let history = &self.state.result.history;
+                let accepted = history[history.len().saturating_sub(adaptive.interval)..]
+                    .iter()
+                    .filter(|h| h.outcome.accepted())
+                    .count();
+                let new_step_size = adaptive.adjust(self.state.result.step_size, accepted);
+                info!(
+                    "step size changed from {} to {}, acceptance rate {}/{}",
+                    self.state.result.step_size, new_step_size, accepted, adaptive.interval
+                );
+                self.state.result.step_size = new_step_size;
+            }
+        }
+        self.state.next_step = i + 1;
+        self.state.write(&self.path)?;
+        // only once the manifest no longer refers to them
+        remove_checkpoints(&self.path, &removed)?;
+
+        let observer = config.observer.as_mut();
+        if let Some(record) = self.state.result.history.last() {
+            if outcome.accepted() {
+                stop |= observer.on_accept(record, &self.varmap).is_break();
+            } else {
+                stop |= observer.on_reject(record).is_break();
+            }
+            if outcome == HopOutcome::NewGlobalMin {
+                stop |= observer.on_new_global_min(record, &self.varmap).is_break();
+            }
+        }
+        if stop {
+            info!("stopped by observer after step {}", i);
+            return Ok(ControlFlow::Break(()));
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Perturb the variables (except at the first step) and relax them into their basin,
+    /// returning the relaxation, the L2 term and the loss including it
+    fn perturb_and_relax(
+        &mut self,
+        config: &mut BhopConfig,
+        minimiser: &mut impl LocalMinimiser<M>,
+        i: usize,
+        step_size: f64,
+    ) -> Result<(Relaxation, f64, f64)> {
+        let (names, vars): (Vec<_>, Vec<_>) = masked_vars(&self.varmap, config.mask.as_ref())
+            .into_iter()
+            .unzip();
+        if i > 0 {
+            match &config.step_scale {
+                None => config
+                    .step_taker
+                    .take_step(&vars, step_size, &mut self.state.rng)?,
+                Some(scale) => {
+                    for (name, var) in names.iter().zip(&vars) {
+                        let size = step_size * scale.factor(name, var)?;
+                        config.step_taker.take_step(
+                            std::slice::from_ref(var),
+                            size,
+                            &mut self.state.rng,
+                        )?;
+                    }
+                }
+            }
+        }
+        // the L2 term only covers the relaxed variables, which the minimiser regularises
+        let relaxation = minimiser.minimise(self.model, vars.clone(), config.observer.as_mut())?;
+
+        #[allow(clippy::cast_possible_truncation)]
+        let l2_fac = if let Some(reg) = config.l2_reg {
+            (l2_norm(&vars)? * reg) as f64
+        } else {
+            0.
+        };
+        Ok((relaxation, l2_fac, relaxation.loss + l2_fac))
+    }
+
+    /// Apply the Metropolis criterion to the new minimum `name`, updating the current and
+    /// global minima, or resetting the variables to the current minimum on rejection
+    fn metropolis(&mut self, loss: f64, name: &str, temperature: f64) -> Result<HopOutcome> {
+        let outcome = if loss < self.state.result.min_loss {
+            // new minimum
+            info!(
+                "new global min from {} to {}",
+                self.state.result.min_loss, loss
+            );
+            info!(
+                "STEP: decrease in loss from {} to {}",
+                self.state.current_loss, loss
+            );
+            self.state.result.min_loss = loss;
+            self.state.result.min_name = name.to_string();
+            // by definition lower than previous value
+            self.state.current_loss = loss;
+            self.state.current_name = name.to_string();
+            HopOutcome::NewGlobalMin
+        } else if loss < self.state.current_loss {
+            info!(
+                "STEP: decrease in loss from {} to {}",
+                self.state.current_loss, loss
+            );
+            self.state.current_loss = loss;
+            self.state.current_name = name.to_string();
+            HopOutcome::Decrease
+        } else {
+            let delta = loss - self.state.current_loss;
+            let p = (-delta / temperature).exp(); // T = temp in units of Kb so P = exp(-delta/T)
+            let n = self.state.rng.gen_range(0_f64..1.);
+            if n < p {
+                info!(
+                    "STEP: accepted MH, from {} to {}",
+                    self.state.current_loss, loss
+                );
+                self.state.current_loss = loss;
+                self.state.current_name = name.to_string();
+                HopOutcome::AcceptedMetropolis
+            } else {
+                // reject
+                info!(
+                    "NOSTEP: rejected MH, loss {}, proposed {}",
+                    self.state.current_loss, loss
+                );
+                self.restore_current()?;
+                HopOutcome::Rejected
+            }
+        };
+        Ok(outcome)
+    }
+
+    /// Reset the variables to the current minimum
+    pub fn restore_current(&mut self) -> Result<()> {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.restore(&self.varmap)?,
+            None => self.varmap.load(self.path.join(&self.state.current_name))?,
+        }
+        Ok(())
+    }
+}